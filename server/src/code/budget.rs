@@ -0,0 +1,97 @@
+//! Limits on how long user code can run for
+//!
+//! There's two levels to this: each individual decision has a limit on both the number of steps it
+//! can take and the wall-clock time it can run for, and the race as a whole has a limit on the
+//! total time spent running user code. Going over the per-decision limit is treated as a soft
+//! error by the simulation (the car keeps its previous directions), whereas going over the
+//! per-race limit ends the race.
+//!
+//! Wall-clock time depends on how busy the server is, so a race where a decision timed out can't
+//! be reproduced exactly. With [deterministic](ExecLimits::deterministic) limits, only the step
+//! limit affects the race. The time limits are still enforced, but as a hard stop: going over
+//! either of them abandons the race without a score, rather than scoring it as a code error.
+
+use std::time::{Duration, Instant};
+
+use super::CodeError;
+
+/// The configurable limits on user code, set once for the server
+#[derive(Copy, Clone, Debug)]
+pub struct ExecLimits {
+    /// The maximum number of steps in a single decision. What a "step" is depends on the backend:
+    /// it's an expression in our script language, a line of Python, and a few units of fuel
+    /// (roughly, instructions) for WebAssembly
+    pub steps_per_call: u64,
+    /// The maximum wall-clock time for a single decision
+    pub time_per_call: Duration,
+    /// The maximum total wall-clock time spent running user code over an entire race
+    pub time_per_race: Duration,
+    /// The maximum size of the [memory](super::Memory) kept between decisions, in cells
+    pub memory_cells: usize,
+    /// Whether the race has to be exactly reproducible. If it does, user code isn't told how much
+    /// time it has left, and going over either time limit abandons the race without a score
+    pub deterministic: bool,
+}
+
+/// The limits for a single decision, as handed to the individual backends
+#[derive(Copy, Clone, Debug)]
+pub struct CallLimits {
+    pub steps: u64,
+    pub deadline: Instant,
+}
+
+impl CallLimits {
+    /// The amount of time left before the deadline
+    pub fn time_remaining(&self) -> Duration {
+        self.deadline.saturating_duration_since(Instant::now())
+    }
+}
+
+/// Tracks how much of the per-race time budget has been used
+pub struct Budget {
+    limits: ExecLimits,
+    used: Duration,
+}
+
+impl Budget {
+    pub fn new(limits: ExecLimits) -> Self {
+        Budget {
+            limits,
+            used: Duration::from_secs(0),
+        }
+    }
+
+    /// Whether the limits require the race to be exactly reproducible
+    pub fn deterministic(&self) -> bool {
+        self.limits.deterministic
+    }
+
+    /// The total time remaining for the race
+    pub fn remaining(&self) -> Duration {
+        self.limits.time_per_race.saturating_sub(self.used)
+    }
+
+    /// Produces the limits for the next decision, or an error if the race budget has run out
+    ///
+    /// The deadline for the decision is cut short if there isn't enough left in the race budget
+    /// for a full one.
+    pub fn next_call(&self) -> Result<CallLimits, CodeError> {
+        let remaining = self.remaining();
+        if remaining == Duration::from_secs(0) {
+            return Err(CodeError::RaceBudgetExhausted(format!(
+                "code ran for more than the total limit of {:?} for the race",
+                self.limits.time_per_race
+            )));
+        }
+
+        Ok(CallLimits {
+            steps: self.limits.steps_per_call,
+            deadline: Instant::now() + remaining.min(self.limits.time_per_call),
+        })
+    }
+
+    /// Records the time taken by a decision
+    pub fn record(&mut self, time: Duration) {
+        self.used += time;
+    }
+}
@@ -0,0 +1,149 @@
+//! Parsing for the settings header at the top of a submission
+//!
+//! A submission can start with any number of `#!` lines, each giving a single `key = value`
+//! setting:
+//! ```text
+//! #! language = python
+//! #! ray_angles = -45, 0, 45
+//! #! car_speed = 7
+//! ```
+//! All of the languages we accept treat these lines as comments, so the rest of the submission can
+//! be handed to its backend unchanged. The header ends at the first line that doesn't start with
+//! `#!`.
+//!
+//! The settings are only checked for syntax here. Whether the values are actually allowed (e.g.
+//! whether a sensor rig or car setup is within the limits) is up to the simulation.
+
+use crate::sim::{Angle, CarSetup, SensorRig, MAX_RAYS};
+
+/// The settings given in a submission's header, with defaults for anything not provided
+pub struct Header {
+    pub language: Language,
+    pub sensors: SensorRig,
+    pub setup: CarSetup,
+}
+
+/// The languages that a submission can be written in
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Language {
+    /// Our own driving-script language, implemented in [`super::script`]
+    Script,
+    /// Python, run with an embedded interpreter by [`super::python`]
+    Python,
+}
+
+impl Default for Header {
+    fn default() -> Self {
+        Header {
+            language: Language::Script,
+            sensors: SensorRig::default(),
+            setup: CarSetup::default(),
+        }
+    }
+}
+
+impl Header {
+    /// Parses the header from the start of the submission
+    pub fn parse(input: &str) -> Result<Header, String> {
+        let mut header = Header::default();
+        // The line that the rays were set on, if they were. Only one of `rays` and `ray_angles`
+        // can be given
+        let mut rays_line = None;
+
+        for (i, line) in input.lines().enumerate() {
+            let setting = match line.strip_prefix("#!") {
+                Some(s) => s,
+                None => break,
+            };
+
+            // Lines are indexed from 1 in error messages
+            let line_no = i + 1;
+            let eq_idx = setting.find('=').ok_or_else(|| {
+                format!("header error on line {}: expected 'key = value'", line_no)
+            })?;
+            let key = setting[..eq_idx].trim();
+            let value = setting[eq_idx + 1..].trim();
+
+            match key {
+                "language" => {
+                    header.language = match value {
+                        "script" => Language::Script,
+                        "python" => Language::Python,
+                        _ => {
+                            return Err(format!(
+                                "header error on line {}: unknown language '{}', expected 'script' or 'python'",
+                                line_no, value
+                            ))
+                        }
+                    }
+                }
+                "rays" | "ray_angles" => {
+                    if let Some(prev) = rays_line {
+                        return Err(format!(
+                            "header error on line {}: the rays were already set on line {}",
+                            line_no, prev
+                        ));
+                    }
+                    rays_line = Some(line_no);
+
+                    header.sensors.angles = if key == "rays" {
+                        let rays = value.parse().map_err(|_| {
+                            format!(
+                                "header error on line {}: expected a whole number of rays, found '{}'",
+                                line_no, value
+                            )
+                        })?;
+                        // This would be caught when the rig is validated, but we'd have to
+                        // allocate all of the rays first
+                        if rays > MAX_RAYS {
+                            return Err(format!(
+                                "header error on line {}: there can be at most {} rays",
+                                line_no, MAX_RAYS
+                            ));
+                        }
+                        SensorRig::evenly_spaced(rays).angles
+                    } else {
+                        let mut angles = Vec::new();
+                        for a in value.split(',') {
+                            angles.push(Angle::from_degrees(parse_num(line_no, a)?));
+                        }
+                        angles
+                    };
+                }
+                "ray_range" => header.sensors.range = parse_num(line_no, value)?,
+                "ray_noise" => header.sensors.noise = parse_num(line_no, value)?,
+                "car_speed" => header.setup.speed = parse_points(line_no, value)?,
+                "car_acceleration" => header.setup.acceleration = parse_points(line_no, value)?,
+                "car_braking" => header.setup.braking = parse_points(line_no, value)?,
+                "car_turning" => header.setup.turning = parse_points(line_no, value)?,
+                _ => {
+                    return Err(format!(
+                        "header error on line {}: unknown setting '{}'",
+                        line_no, key
+                    ))
+                }
+            }
+        }
+
+        Ok(header)
+    }
+}
+
+fn parse_num(line_no: usize, value: &str) -> Result<f32, String> {
+    let value = value.trim();
+    value.parse().map_err(|_| {
+        format!(
+            "header error on line {}: expected a number, found '{}'",
+            line_no, value
+        )
+    })
+}
+
+fn parse_points(line_no: usize, value: &str) -> Result<u32, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "header error on line {}: expected a whole number of points, found '{}'",
+            line_no, value
+        )
+    })
+}
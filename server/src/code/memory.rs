@@ -0,0 +1,84 @@
+//! Memory that user code keeps between decisions
+//!
+//! Each backend runs a decision from a clean slate, so anything a submission wants to remember
+//! over the course of a race (a PID controller's integral, the previous distances to the wall,
+//! which lap it's on) has to go through a [`Memory`]. There's one for each simulation, and it's
+//! limited in size so that a submission can't use it to eat up the server's memory.
+
+use std::collections::HashMap;
+
+/// A single value held in memory. These are the values common to all of the backends
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stored {
+    Num(f32),
+    Bool(bool),
+    List(Vec<Stored>),
+}
+
+impl Stored {
+    /// The number of cells that the value takes up, counting one for each number or boolean and
+    /// one for each list
+    pub fn cells(&self) -> usize {
+        match self {
+            Stored::Num(_) | Stored::Bool(_) => 1,
+            Stored::List(items) => 1 + items.iter().map(Stored::cells).sum::<usize>(),
+        }
+    }
+}
+
+/// The named values that a submission has stored over the course of a race
+#[derive(Clone, Debug)]
+pub struct Memory {
+    values: HashMap<String, Stored>,
+    /// The maximum total number of cells in `values`
+    limit: usize,
+}
+
+impl Memory {
+    /// Creates an empty memory, which can hold at most `limit` cells
+    pub fn new(limit: usize) -> Self {
+        Memory {
+            values: HashMap::new(),
+            limit,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Stored> {
+        self.values.get(name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Stored)> {
+        self.values.iter()
+    }
+
+    /// The maximum total number of cells that the memory can hold
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    /// The total number of cells currently in use
+    pub fn used(&self) -> usize {
+        self.values.values().map(Stored::cells).sum()
+    }
+
+    /// Stores the value under `name`, replacing what was there before. This fails, without
+    /// changing anything, if it would take the memory over its limit
+    pub fn set(&mut self, name: &str, value: Stored) -> Result<(), String> {
+        let old = self.values.get(name).map_or(0, Stored::cells);
+        let new = self.used() - old + value.cells();
+        if new > self.limit {
+            return Err(format!(
+                "storing '{}' would use {} cells of memory, but the limit is {}",
+                name, new, self.limit
+            ));
+        }
+
+        self.values.insert(name.to_owned(), value);
+        Ok(())
+    }
+
+    /// Removes everything from memory
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
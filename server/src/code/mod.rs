@@ -0,0 +1,245 @@
+//! Wrapper module for parsing and executing user-submitted code
+//!
+//! Text submissions are either written in our own small driving-script language (implemented in
+//! the [`script`] submodule) or in Python (run by the [`python`] submodule). Which one is used is
+//! given by the [header](header) at the top of the submission, defaulting to the script language.
+//! Users can alternatively submit a compiled WebAssembly module, which is run by the [`wasm`]
+//! submodule.
+
+pub use crate::sim::{Point,Car};
+use crate::sim::{CarSetup, SensorRig, NUM_GEARS};
+
+use std::fmt::{self, Display, Formatter};
+use std::time::{Duration, Instant};
+
+mod budget;
+mod header;
+mod memory;
+mod python;
+mod script;
+mod wasm;
+
+pub use budget::{Budget, CallLimits, ExecLimits};
+use header::{Header, Language};
+pub use memory::{Memory, Stored};
+
+/// A submission, exactly as it was sent to us
+#[derive(Clone)]
+pub enum Source {
+    /// Source code, in one of the languages selectable by the header
+    Text(String),
+    /// A compiled WebAssembly module
+    Wasm(Vec<u8>),
+}
+
+impl Source {
+    /// The size of the submission, in bytes
+    pub fn size(&self) -> usize {
+        match self {
+            Source::Text(s) => s.len(),
+            Source::Wasm(bytes) => bytes.len(),
+        }
+    }
+}
+
+/// User-submitted code - parsed, checked and ready to be executed
+pub struct Code {
+    backend: Backend,
+    sensors: SensorRig,
+    setup: CarSetup,
+}
+
+/// The different ways that we can run user code
+enum Backend {
+    Script(script::Program),
+    Python(python::Module),
+    Wasm(Box<wasm::Module>),
+}
+
+/// The ways that parsing or executing user code can fail
+#[derive(Debug)]
+pub enum CodeError {
+    /// The code couldn't be parsed or compiled
+    Parse(String),
+    /// The code failed while running
+    Runtime(String),
+    /// A single decision went over its step or time limit
+    Timeout(String),
+    /// The total time spent running the code over the race went over its limit
+    RaceBudgetExhausted(String),
+    /// A single decision went over its time limit while the limits were
+    /// [deterministic](ExecLimits::deterministic). Timing out would make the result depend on how
+    /// fast the server happened to be, so this abandons the race instead
+    TooSlow(String),
+}
+
+impl CodeError {
+    /// Whether the error came from running out of wall-clock time for the whole race or (with
+    /// [deterministic](ExecLimits::deterministic) limits) a single decision. These depend on how
+    /// busy the server is, so with deterministic limits the race is abandoned without a score
+    pub fn is_wall_clock(&self) -> bool {
+        matches!(
+            self,
+            CodeError::RaceBudgetExhausted(_) | CodeError::TooSlow(_)
+        )
+    }
+}
+
+impl Display for CodeError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CodeError::Parse(msg)
+            | CodeError::Runtime(msg)
+            | CodeError::Timeout(msg)
+            | CodeError::RaceBudgetExhausted(msg)
+            | CodeError::TooSlow(msg) => f.write_str(msg),
+        }
+    }
+}
+
+//TODO: Work out how code simulation actually works
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Output {
+    pub acc: f32, // fraction of how much the pedal is down - Between -1 and 1 negative being breaking
+    pub turning_speed: f32, // Speed in degree/tick to turn - limited by `Car::max_yaw_rate`
+    pub gear: u8, // Gear to drive in, from 1 to `NUM_GEARS` - or 0 to let the gearbox choose
+}
+
+// Checks a gear given by user code, which could be any number, and converts it for `Output`
+fn gear_from(gear: f64) -> Result<u8, String> {
+    if gear.fract() != 0.0 || !(0.0..=NUM_GEARS as f64).contains(&gear) {
+        return Err(format!(
+            "gear must be a whole number between 0 and {}, but it's {}",
+            NUM_GEARS, gear
+        ));
+    }
+
+    Ok(gear as u8)
+}
+
+/// The execution environment for user-submitted code, providing information about the state of the
+/// car in its race
+// TODO - Consider if this is actually what we want
+pub struct ExecEnvironment {
+    pub car_currently : Car, // Gives current information about the car
+    /// The distance to the wall along each of the rays in the code's [sensor rig](SensorRig), in
+    /// the same order
+    pub dist_to_wall: Vec<f32>,
+
+    /// The number of ticks since the start of the race
+    pub tick: i32,
+    /// The number of laps left to finish, including the one currently in progress
+    pub laps_remaining: i32,
+    /// The straight-line distance from the car to the middle of the finish line
+    pub dist_to_finish: f32,
+    /// The angle from the direction the car is facing to the middle of the finish line, in radians
+    /// between -pi and pi. Positive angles are anticlockwise
+    pub bearing_to_finish: f32,
+    /// The velocity of the car, in units per tick
+    pub velocity: Point,
+    /// The directions that the car has been following since the last decision. Both are zero
+    /// before the first one
+    pub previous_output: Output,
+    /// The amount of time left from the race's budget for running user code. With
+    /// [deterministic](ExecLimits::deterministic) limits, this is always the full budget
+    pub time_remaining: Duration,
+}
+
+
+impl Code {
+    /// Parses or compiles any kind of submission
+    pub fn from_source(source: &Source) -> Result<Code, CodeError> {
+        match source {
+            Source::Text(s) => Code::from_str(s),
+            Source::Wasm(bytes) => Code::from_wasm(bytes),
+        }
+    }
+
+    /// Parses the user's code, returning any error if there was one
+    ///
+    /// Parse errors are given with the line and column they occured at.
+    pub fn from_str(input: &str) -> Result<Code, CodeError> {
+        let header = Header::parse(input).map_err(CodeError::Parse)?;
+
+        let backend = match header.language {
+            Language::Script => script::Program::parse(input).map(Backend::Script),
+            Language::Python => python::Module::compile(input).map(Backend::Python),
+        };
+
+        Ok(Code {
+            backend: backend.map_err(CodeError::Parse)?,
+            sensors: header.sensors,
+            setup: header.setup,
+        })
+    }
+
+    /// Compiles the user's WebAssembly module, checking that it has the exports we expect
+    ///
+    /// Modules don't have a header, so they always use the default settings.
+    pub fn from_wasm(bytes: &[u8]) -> Result<Code, CodeError> {
+        let module = wasm::Module::compile(bytes).map_err(CodeError::Parse)?;
+        Ok(Code {
+            backend: Backend::Wasm(Box::new(module)),
+            sensors: SensorRig::default(),
+            setup: CarSetup::default(),
+        })
+    }
+
+    /// The sensor rig that the code asked for, which hasn't been validated yet
+    pub fn sensors(&self) -> &SensorRig {
+        &self.sensors
+    }
+
+    /// The car setup that the code asked for, which hasn't been validated yet
+    pub fn setup(&self) -> &CarSetup {
+        &self.setup
+    }
+
+    /// Execute's the users's code within the given race environment, returning the output as an
+    /// in-Rust directive for the car's movement
+    ///
+    /// The time taken is charged to `budget`. A decision that goes over its own limits gives a
+    /// [`CodeError::Timeout`], even if the code managed to finish - unless the limits are
+    /// [deterministic](ExecLimits::deterministic) and it was the time limit, which gives a
+    /// [`CodeError::TooSlow`] instead.
+    ///
+    /// Changes to `memory` are only kept if the decision succeeds, so a decision that fails part
+    /// of the way through can't leave it half-updated.
+    pub fn execute(
+        &self,
+        env: &ExecEnvironment,
+        budget: &mut Budget,
+        memory: &mut Memory,
+    ) -> Result<Output, CodeError> {
+        let limits = budget.next_call()?;
+        let mut scratch = memory.clone();
+        let start = Instant::now();
+
+        let result = match &self.backend {
+            Backend::Script(program) => program.run(env, &limits, &mut scratch),
+            Backend::Python(module) => module.run(env, &limits, &mut scratch),
+            Backend::Wasm(module) => module.run(env, &limits, &mut scratch),
+        };
+
+        budget.record(start.elapsed());
+
+        // The backends might have stopped with a `Timeout` because of the time limit, so this has
+        // to be checked first
+        if budget.deterministic() && Instant::now() >= limits.deadline {
+            return Err(CodeError::TooSlow(
+                "decision took longer than its time limit, so the race can't be reproduced"
+                    .to_owned(),
+            ));
+        }
+
+        let output = result?;
+        if Instant::now() > limits.deadline {
+            return Err(CodeError::Timeout(
+                "decision took longer than its time limit".to_owned(),
+            ));
+        }
+
+        *memory = scratch;
+        Ok(output)
+    }
+}
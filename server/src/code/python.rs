@@ -0,0 +1,562 @@
+//! Python driver scripts, run with an embedded interpreter through pyo3
+//!
+//! A Python submission is executed once as a module, which must define a function
+//! `drive(car, dist_to_wall)`. That function is then called for every decision, and returns either
+//! `(acc, turning_speed)` or `(acc, turning_speed, gear)`. The rest of the environment is given by
+//! the global `env`, which is updated before each call. Similarly, the global `memory` is set to a
+//! dict holding the contents of the race's [`Memory`]; whatever is in it afterwards is written
+//! back.
+//!
+//! The module doesn't get the usual set of builtins. It only has access to the (harmless) ones in
+//! [`ALLOWED_BUILTINS`], plus a stand-in for the `math` module, so there's no `__import__`, `open`,
+//! `exec` or similar. Because restricting the builtins on its own is famously easy to get around,
+//! we also reject any source that uses `import`, refers to a name starting with a double
+//! underscore, or uses one of the attributes that reach the interpreter's frames (like a
+//! generator's `gi_frame`), before we run any of it. Without that last check, a script could walk
+//! up the stack from a frame to the globals of our own support code, which have the full set of
+//! builtins.
+//!
+//! All of the submission's code runs under a trace function that counts the lines executed and
+//! checks the clock, so that it can be stopped once it goes over its limits. This can't interrupt
+//! a single long-running builtin, so `range` is replaced with a version that refuses to produce
+//! more than `MAX_RANGE` elements.
+//!
+//! The real `math` module comes from the platform's maths library, so its results can differ
+//! between machines, and races have to be reproducible. The stand-in only has the parts of `math`
+//! that IEEE 754 specifies exactly (like `sqrt` and `floor`), with the trigonometry and logarithms
+//! replaced by the simulation's own [`strict`] versions. Those work in `f32`, the same as the
+//! script language.
+
+use std::time::Duration;
+
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyList, PyLong, PyTuple};
+
+use super::{gear_from, CallLimits, CodeError, ExecEnvironment, Memory, Output, Stored};
+use crate::sim::strict;
+
+/// The builtins that are made available to Python submissions. `range` is also available, but
+/// it's replaced by the limited version from [`SUPPORT_SOURCE`]
+#[rustfmt::skip]
+const ALLOWED_BUILTINS: &[&str] = &[
+    "abs", "all", "any", "bool", "dict", "divmod", "enumerate", "filter", "float", "int", "len",
+    "list", "map", "max", "min", "pow", "reversed", "round", "sorted", "sum", "tuple", "zip",
+    "ArithmeticError", "Exception", "IndexError", "KeyError", "ValueError", "ZeroDivisionError",
+];
+
+/// How deeply lists can be nested inside `memory`. A list that contains itself would otherwise
+/// have us recursing forever while converting it
+const MAX_MEMORY_DEPTH: usize = 32;
+
+/// The limits for running the body of the module when it's first loaded
+const LOAD_STEPS: u64 = 1_000_000;
+const LOAD_TIME: Duration = Duration::from_secs(1);
+
+/// Python source for the support code that we use to check and run submissions. This runs with
+/// the full set of builtins, so none of the submission's code should run outside of `call`.
+const SUPPORT_SOURCE: &str = r#"
+import ast, math, sys, time, types
+
+# Attributes of frames, code objects, tracebacks, generators and coroutines. Any of these would
+# let a script get hold of a frame, and from there the globals of whatever called it
+BLOCKED_ATTRIBUTES = {
+    "gi_frame", "gi_code", "cr_frame", "cr_code", "ag_frame", "ag_code",
+    "f_back", "f_globals", "f_builtins", "f_locals", "f_code", "f_trace",
+    "tb_frame", "tb_next",
+}
+
+def check(source):
+    for node in ast.walk(ast.parse(source, "<driver>")):
+        if isinstance(node, (ast.Import, ast.ImportFrom)):
+            return "line %d: imports are not allowed" % node.lineno
+        name = getattr(node, "attr", None) or getattr(node, "id", None)
+        if isinstance(name, str) and name.startswith("__"):
+            return "line %d: names starting with '__' are not allowed" % node.lineno
+        if isinstance(name, str) and name in BLOCKED_ATTRIBUTES:
+            return "line %d: '%s' is not allowed" % (node.lineno, name)
+        # `str.format` can look up attributes too, as in "{0.gi_frame}".format(generator)
+        if isinstance(node, ast.Constant) and isinstance(node.value, str):
+            if "__" in node.value or any(a in node.value for a in BLOCKED_ATTRIBUTES):
+                return "line %d: strings can't mention '__' or frame attributes" % node.lineno
+    return None
+
+MAX_RANGE = 1000000
+
+# The parts of `math` that give exactly the same results everywhere
+EXACT_MATH = [
+    "ceil", "copysign", "degrees", "fabs", "floor", "fmod", "isfinite", "isinf", "isnan",
+    "radians", "sqrt", "trunc", "e", "inf", "nan", "pi", "tau",
+]
+
+def fresh_math(strict):
+    public = {name: getattr(math, name) for name in EXACT_MATH}
+    public.update(strict)
+    return types.SimpleNamespace(**public)
+
+def limited_range(*args):
+    r = range(*args)
+    if len(r) > MAX_RANGE:
+        raise ValueError("range is too large (the limit is %d elements)" % MAX_RANGE)
+    return r
+
+class Budget:
+    def __init__(self, steps, seconds):
+        self.steps = steps
+        self.deadline = time.monotonic() + seconds
+        self.exceeded = False
+
+    def trace(self, frame, event, arg):
+        self.steps -= 1
+        if self.exceeded or self.steps < 0 or time.monotonic() > self.deadline:
+            self.exceeded = True
+            raise TimeoutError("went over the step or time limit")
+        return self.trace
+
+def call(budget, f, *args):
+    sys.settrace(budget.trace)
+    try:
+        return f(*args)
+    finally:
+        sys.settrace(None)
+
+def load(budget, source, globals):
+    call(budget, exec, compile(source, "<driver>", "exec"), globals)
+"#;
+
+/// A Python submission, with its `drive` function ready to be called
+pub struct Module {
+    support: Py<PyModule>,
+    globals: Py<PyDict>,
+    drive: PyObject,
+}
+
+/// Converts a Python exception into the error message we give back to the user
+fn py_error(py: Python, err: PyErr) -> String {
+    match err.traceback(py).and_then(|tb| tb.format().ok()) {
+        Some(tb) => format!("python error: {}\n{}", err, tb),
+        None => format!("python error: {}", err),
+    }
+}
+
+/// Creates a new `Budget` object from the support module
+fn new_budget(support: &PyModule, steps: u64, time: Duration) -> PyResult<&PyAny> {
+    support
+        .getattr("Budget")?
+        .call1((steps, time.as_secs_f64()))
+}
+
+/// Returns whether the `Budget` object has been used up
+fn budget_exceeded(budget: &PyAny) -> bool {
+    budget
+        .getattr("exceeded")
+        .and_then(|e| e.extract())
+        .unwrap_or(true)
+}
+
+impl Module {
+    /// Checks and runs the Python source as a module, extracting its `drive` function
+    pub fn compile(source: &str) -> Result<Module, String> {
+        Python::with_gil(|py| {
+            let support = PyModule::from_code(py, SUPPORT_SOURCE, "support.py", "support")
+                .map_err(|e| py_error(py, e))?;
+
+            // Any exception from the check will be a syntax error in the source. The traceback
+            // would only point into our own checking code, so we leave it out.
+            let problem: Option<String> = support
+                .getattr("check")
+                .and_then(|check| check.call1((source,)))
+                .and_then(|res| res.extract())
+                .map_err(|e| format!("python error: {}", e))?;
+            if let Some(p) = problem {
+                return Err(format!("python error: {}", p));
+            }
+
+            let globals = Self::restricted_globals(py, support).map_err(|e| py_error(py, e))?;
+            let budget = new_budget(support, LOAD_STEPS, LOAD_TIME).map_err(|e| py_error(py, e))?;
+            let loaded = support
+                .getattr("load")
+                .and_then(|load| load.call1((budget, source, globals)));
+
+            if budget_exceeded(budget) {
+                return Err(format!(
+                    "python error: loading the module took more than {} lines or {:?}",
+                    LOAD_STEPS, LOAD_TIME
+                ));
+            }
+            loaded.map_err(|e| py_error(py, e))?;
+
+            let drive = match globals.get_item("drive").map_err(|e| py_error(py, e))? {
+                Some(d) if d.is_callable() => d,
+                Some(_) => return Err("python error: 'drive' must be a function".to_owned()),
+                None => return Err("python error: no 'drive' function defined".to_owned()),
+            };
+
+            Ok(Module {
+                support: support.into(),
+                globals: globals.into(),
+                drive: drive.into(),
+            })
+        })
+    }
+
+    /// Produces the globals that the submission is executed with
+    fn restricted_globals<'py>(py: Python<'py>, support: &PyModule) -> PyResult<&'py PyDict> {
+        let builtins = py.import("builtins")?;
+        let allowed = PyDict::new(py);
+        for name in ALLOWED_BUILTINS {
+            allowed.set_item(*name, builtins.getattr(*name)?)?;
+        }
+        allowed.set_item("range", support.getattr("limited_range")?)?;
+
+        let globals = PyDict::new(py);
+        globals.set_item("__builtins__", allowed)?;
+        globals.set_item("__name__", "driver")?;
+        // Every submission gets its own copy of `math`. The module itself is shared by the whole
+        // interpreter, so anything a submission did to it would carry over into later races
+        let math = support.getattr("fresh_math")?.call1((strict_math(py)?,))?;
+        globals.set_item("math", math)?;
+        globals.set_item("env", py.None())?;
+        globals.set_item("memory", PyDict::new(py))?;
+        Ok(globals)
+    }
+
+    /// Calls the submission's `drive` function for the given environment
+    pub fn run(
+        &self,
+        env: &ExecEnvironment,
+        limits: &CallLimits,
+        memory: &mut Memory,
+    ) -> Result<Output, CodeError> {
+        Python::with_gil(|py| {
+            let support = self.support.as_ref(py);
+            let budget = new_budget(support, limits.steps, limits.time_remaining())
+                .map_err(|e| CodeError::Runtime(py_error(py, e)))?;
+
+            let globals = self.globals.as_ref(py);
+            env_to_py(py, env)
+                .and_then(|ns| globals.set_item("env", ns))
+                .and_then(|_| memory_to_py(py, memory))
+                .and_then(|dict| globals.set_item("memory", dict))
+                .map_err(|e| CodeError::Runtime(py_error(py, e)))?;
+
+            let result = self.call_drive(py, env, budget);
+
+            // The script could have caught the `TimeoutError` from going over budget, so we
+            // check the budget itself instead of the result
+            if budget_exceeded(budget) {
+                return Err(CodeError::Timeout(
+                    "python error: drive went over its step or time limit".to_owned(),
+                ));
+            }
+
+            let (acc, turning_speed, gear) =
+                result.map_err(|e| CodeError::Runtime(py_error(py, e)))?;
+
+            if !acc.is_finite() || !turning_speed.is_finite() {
+                return Err(CodeError::Runtime(format!(
+                    "python error: drive returned non-finite output ({}, {})",
+                    acc, turning_speed
+                )));
+            }
+
+            let gear = gear_from(gear)
+                .map_err(|msg| CodeError::Runtime(format!("python error: {}", msg)))?;
+
+            let dict = globals
+                .get_item("memory")
+                .map_err(|e| CodeError::Runtime(py_error(py, e)))?;
+            memory_from_py(dict, memory)
+                .map_err(|msg| CodeError::Runtime(format!("python error: {}", msg)))?;
+
+            Ok(Output {
+                acc,
+                turning_speed,
+                gear,
+            })
+        })
+    }
+
+    fn call_drive(
+        &self,
+        py: Python,
+        env: &ExecEnvironment,
+        budget: &PyAny,
+    ) -> PyResult<(f32, f32, f64)> {
+        let car = env.car_currently;
+        let car = namespace(
+            py,
+            &[
+                ("x", car.pos.x.into_py(py)),
+                ("y", car.pos.y.into_py(py)),
+                ("angle", car.angle.radians().into_py(py)),
+                ("speed", car.speed.into_py(py)),
+                ("slip", car.slip.radians().into_py(py)),
+                ("gear", car.gear.into_py(py)),
+                ("rpm", car.rpm.into_py(py)),
+                ("max_speed", car.max_speed.into_py(py)),
+                ("max_turn", car.max_turn.degrees().into_py(py)),
+            ],
+        )?;
+
+        let dist_to_wall = PyList::new(py, &env.dist_to_wall);
+
+        let output = self.support.as_ref(py).getattr("call")?.call1((
+            budget,
+            &self.drive,
+            car,
+            dist_to_wall,
+        ))?;
+
+        // The gear is optional, and leaving it out lets the gearbox choose
+        match output.extract() {
+            Ok(output) => Ok(output),
+            Err(_) => output
+                .extract()
+                .map(|(acc, turning_speed)| (acc, turning_speed, 0.0)),
+        }
+    }
+}
+
+/// Creates a dict of the functions that replace the parts of `math` that aren't exactly specified
+fn strict_math<'py>(py: Python<'py>) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    dict.set_item("sin", wrap_pyfunction!(strict_sin, py)?)?;
+    dict.set_item("cos", wrap_pyfunction!(strict_cos, py)?)?;
+    dict.set_item("tan", wrap_pyfunction!(strict_tan, py)?)?;
+    dict.set_item("atan", wrap_pyfunction!(strict_atan, py)?)?;
+    dict.set_item("atan2", wrap_pyfunction!(strict_atan2, py)?)?;
+    dict.set_item("hypot", wrap_pyfunction!(strict_hypot, py)?)?;
+    dict.set_item("log", wrap_pyfunction!(strict_log, py)?)?;
+    Ok(dict)
+}
+
+#[pyfunction]
+#[pyo3(name = "sin")]
+fn strict_sin(x: f64) -> f64 {
+    strict::sin(x as f32).into()
+}
+
+#[pyfunction]
+#[pyo3(name = "cos")]
+fn strict_cos(x: f64) -> f64 {
+    strict::cos(x as f32).into()
+}
+
+#[pyfunction]
+#[pyo3(name = "tan")]
+fn strict_tan(x: f64) -> f64 {
+    strict::tan(x as f32).into()
+}
+
+#[pyfunction]
+#[pyo3(name = "atan")]
+fn strict_atan(x: f64) -> f64 {
+    strict::atan2(x as f32, 1.0).into()
+}
+
+#[pyfunction]
+#[pyo3(name = "atan2")]
+fn strict_atan2(y: f64, x: f64) -> f64 {
+    strict::atan2(y as f32, x as f32).into()
+}
+
+// Only basic arithmetic, so this is exact without any help
+#[pyfunction]
+#[pyo3(name = "hypot")]
+fn strict_hypot(x: f64, y: f64) -> f64 {
+    (x * x + y * y).sqrt()
+}
+
+#[pyfunction]
+#[pyo3(name = "log", signature = (x, base = None))]
+fn strict_log(x: f64, base: Option<f64>) -> f64 {
+    let ln = f64::from(strict::ln(x as f32));
+    match base {
+        Some(base) => ln / f64::from(strict::ln(base as f32)),
+        None => ln,
+    }
+}
+
+/// Creates a `SimpleNamespace` with the given fields, so that scripts can write `car.speed`, the
+/// same as in our own script language
+fn namespace<'py>(py: Python<'py>, fields: &[(&str, PyObject)]) -> PyResult<&'py PyAny> {
+    let dict = PyDict::new(py);
+    for (name, value) in fields {
+        dict.set_item(*name, value)?;
+    }
+    py.import("types")?
+        .getattr("SimpleNamespace")?
+        .call((), Some(dict))
+}
+
+/// Creates the `env` namespace with the parts of the environment not given directly to `drive`
+fn env_to_py<'py>(py: Python<'py>, env: &ExecEnvironment) -> PyResult<&'py PyAny> {
+    let velocity = namespace(
+        py,
+        &[
+            ("x", env.velocity.x.into_py(py)),
+            ("y", env.velocity.y.into_py(py)),
+        ],
+    )?;
+    let previous = namespace(
+        py,
+        &[
+            ("acc", env.previous_output.acc.into_py(py)),
+            (
+                "turning_speed",
+                env.previous_output.turning_speed.into_py(py),
+            ),
+            ("gear", env.previous_output.gear.into_py(py)),
+        ],
+    )?;
+
+    namespace(
+        py,
+        &[
+            ("tick", env.tick.into_py(py)),
+            ("laps_remaining", env.laps_remaining.into_py(py)),
+            ("dist_to_finish", env.dist_to_finish.into_py(py)),
+            ("bearing_to_finish", env.bearing_to_finish.into_py(py)),
+            ("velocity", velocity.into_py(py)),
+            ("previous", previous.into_py(py)),
+            (
+                "time_remaining",
+                env.time_remaining.as_secs_f64().into_py(py),
+            ),
+        ],
+    )
+}
+
+fn stored_to_py(py: Python, value: &Stored) -> PyObject {
+    match value {
+        Stored::Num(n) => n.into_py(py),
+        Stored::Bool(b) => b.into_py(py),
+        Stored::List(items) => {
+            PyList::new(py, items.iter().map(|item| stored_to_py(py, item))).into_py(py)
+        }
+    }
+}
+
+// Converts a value from `memory`, `depth` lists deep. Every value converted is counted in `cells`,
+// which can't go over `max_cells`, so that a huge value fails as soon as it's too big instead of
+// after it's all been copied
+fn stored_from_py(
+    value: &PyAny,
+    depth: usize,
+    cells: &mut usize,
+    max_cells: usize,
+) -> Result<Stored, String> {
+    *cells += 1;
+    if *cells > max_cells {
+        return Err(format!(
+            "memory can only hold {} cells, but it has more than that",
+            max_cells
+        ));
+    }
+
+    // `bool` is a subclass of `int`, so it has to be checked first
+    if value.is_instance_of::<PyBool>() {
+        Ok(Stored::Bool(value.is_true().map_err(|e| e.to_string())?))
+    } else if value.is_instance_of::<PyLong>() || value.is_instance_of::<PyFloat>() {
+        let n: f64 = value.extract().map_err(|e| e.to_string())?;
+        Ok(Stored::Num(n as f32))
+    } else if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        if depth >= MAX_MEMORY_DEPTH {
+            return Err(format!(
+                "lists in memory can only be nested {} deep (does a list contain itself?)",
+                MAX_MEMORY_DEPTH
+            ));
+        }
+
+        let items = value.iter().map_err(|e| e.to_string())?;
+        let mut stored = Vec::new();
+        for item in items {
+            let item = item.map_err(|e| e.to_string())?;
+            stored.push(stored_from_py(item, depth + 1, cells, max_cells)?);
+        }
+        Ok(Stored::List(stored))
+    } else {
+        Err(format!(
+            "memory can only hold numbers, booleans and lists, not '{}'",
+            value.get_type().name().unwrap_or("?")
+        ))
+    }
+}
+
+/// Creates the `memory` dict that's given to the submission
+fn memory_to_py<'py>(py: Python<'py>, memory: &Memory) -> PyResult<&'py PyDict> {
+    let dict = PyDict::new(py);
+    for (name, value) in memory.iter() {
+        dict.set_item(name, stored_to_py(py, value))?;
+    }
+    Ok(dict)
+}
+
+/// Replaces the contents of `memory` with those of the `memory` dict after a call
+fn memory_from_py(dict: Option<&PyAny>, memory: &mut Memory) -> Result<(), String> {
+    let dict: &PyDict = match dict {
+        Some(d) => d
+            .downcast()
+            .map_err(|_| "'memory' must be a dict".to_owned())?,
+        None => return Err("'memory' must be a dict".to_owned()),
+    };
+
+    let max_cells = memory.limit();
+    let mut cells = 0;
+    memory.clear();
+    for (name, value) in dict.iter() {
+        let name: &str = name
+            .extract()
+            .map_err(|_| "the keys of 'memory' must be strings".to_owned())?;
+        memory.set(name, stored_from_py(value, 0, &mut cells, max_cells)?)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DRIVE: &str = "def drive(car, dist_to_wall):\n    return (0, 0)\n";
+
+    #[test]
+    fn submissions_get_their_own_math() {
+        let _vandal =
+            Module::compile(&format!("math.pi = 4.0\nmath.sqrt = abs\n{}", DRIVE)).unwrap();
+        let victim =
+            Module::compile(&format!("pi = math.pi\nroot = math.sqrt(4)\n{}", DRIVE)).unwrap();
+
+        Python::with_gil(|py| {
+            let globals = victim.globals.as_ref(py);
+            let get = |name| -> f64 { globals.get_item(name).unwrap().unwrap().extract().unwrap() };
+            assert_eq!(get("pi"), std::f64::consts::PI);
+            assert_eq!(get("root"), 2.0);
+        });
+    }
+
+    #[test]
+    fn math_is_strict() {
+        let source = "values = [math.sin(1), math.atan2(1, 2), math.log(8, 2), math.hypot(3, 4)]\n";
+        let module = Module::compile(&format!("{}{}", source, DRIVE)).unwrap();
+
+        Python::with_gil(|py| {
+            let globals = module.globals.as_ref(py);
+            let values: Vec<f64> = globals
+                .get_item("values")
+                .unwrap()
+                .unwrap()
+                .extract()
+                .unwrap();
+            let expected = [
+                strict::sin(1.0).into(),
+                strict::atan2(1.0, 2.0).into(),
+                f64::from(strict::ln(8.0)) / f64::from(strict::ln(2.0)),
+                5.0,
+            ];
+            assert_eq!(values, expected);
+        });
+
+        // Anything that isn't exactly specified isn't there at all
+        assert!(Module::compile(&format!("x = math.exp(1)\n{}", DRIVE)).is_err());
+    }
+}
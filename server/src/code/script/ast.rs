@@ -0,0 +1,172 @@
+//! The abstract syntax tree for driving scripts, as produced by the [parser](super::parser)
+
+use super::Pos;
+
+#[derive(Debug)]
+pub enum Stmt {
+    /// `name = value`
+    Assign { name: String, value: Expr },
+    /// `persist name = init`
+    ///
+    /// Marks `name` as being kept in memory between decisions. If there's already a stored value,
+    /// `name` is loaded from memory and `init` isn't evaluated at all. Only the first `persist` of
+    /// each name in a decision has any effect.
+    Persist { name: String, init: Expr, pos: Pos },
+    /// `if cond { .. } else if cond { .. } else { .. }`
+    ///
+    /// Each `else if` adds another entry to `branches`; `otherwise` is empty if there's no final
+    /// `else`.
+    If {
+        branches: Vec<(Expr, Vec<Stmt>)>,
+        otherwise: Vec<Stmt>,
+    },
+    /// `while cond { .. }`
+    While {
+        cond: Expr,
+        body: Vec<Stmt>,
+        pos: Pos,
+    },
+    /// `for var in start..end { .. }`, where `end` is exclusive
+    For {
+        var: String,
+        start: Expr,
+        end: Expr,
+        body: Vec<Stmt>,
+        pos: Pos,
+    },
+}
+
+#[derive(Debug)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub pos: Pos,
+}
+
+#[derive(Debug)]
+pub enum ExprKind {
+    Num(f32),
+    Bool(bool),
+    Var(String),
+    List(Vec<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    /// A single comparison, like `a < b`. Comparisons can't be chained
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+    /// A left-associative chain of operators, like `a + b - c`, with the position of each operator
+    ///
+    /// This is kept flat rather than nesting each operator in the next, since a chain can be as
+    /// long as the script and walking a tree that deep could overflow the stack.
+    Chain(Box<Expr>, Vec<(BinaryOp, Pos, Expr)>),
+    /// A call to one of the builtin functions. Calls to unknown functions are rejected by the parser
+    Call(Builtin, Vec<Expr>),
+    /// `list[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `record.field`
+    Field(Box<Expr>, String),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    And,
+    Or,
+}
+
+impl BinaryOp {
+    /// The operator, as it's written in scripts
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+            Self::Eq => "==",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::And => "and",
+            Self::Or => "or",
+        }
+    }
+}
+
+/// The functions available to scripts
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Builtin {
+    Abs,
+    Min,
+    Max,
+    Clamp,
+    Sqrt,
+    Sin,
+    Cos,
+    Tan,
+    Atan2,
+    Floor,
+    Ceil,
+    Round,
+    Len,
+}
+
+/// The name of each builtin, as it's written in scripts
+const BUILTIN_NAMES: &[(&str, Builtin)] = &[
+    ("abs", Builtin::Abs),
+    ("min", Builtin::Min),
+    ("max", Builtin::Max),
+    ("clamp", Builtin::Clamp),
+    ("sqrt", Builtin::Sqrt),
+    ("sin", Builtin::Sin),
+    ("cos", Builtin::Cos),
+    ("tan", Builtin::Tan),
+    ("atan2", Builtin::Atan2),
+    ("floor", Builtin::Floor),
+    ("ceil", Builtin::Ceil),
+    ("round", Builtin::Round),
+    ("len", Builtin::Len),
+];
+
+impl Builtin {
+    /// Looks up the builtin with the given name, if there is one
+    pub fn from_name(name: &str) -> Option<Self> {
+        BUILTIN_NAMES
+            .iter()
+            .find(|(n, _)| *n == name)
+            .map(|(_, b)| *b)
+    }
+
+    /// The name of the builtin, as it's written in scripts
+    pub fn name(&self) -> &'static str {
+        BUILTIN_NAMES
+            .iter()
+            .find(|(_, b)| b == self)
+            .map(|(n, _)| *n)
+            .unwrap()
+    }
+
+    /// The number of arguments that the builtin takes
+    pub fn arity(&self) -> usize {
+        match self {
+            Self::Min | Self::Max | Self::Atan2 => 2,
+            Self::Clamp => 3,
+            _ => 1,
+        }
+    }
+}
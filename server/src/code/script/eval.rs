@@ -0,0 +1,546 @@
+//! Tree-walking evaluator for driving scripts
+//!
+//! Scripts run from top to bottom once per decision. Before they start, the environment is
+//! provided through a few predefined variables (`car`, `dist_to_wall` and `env`); once they finish,
+//! the values left in `acc`, `turning_speed` and `gear` are taken as the car's directions, and any
+//! variables that were `persist`ed are written back to memory.
+//!
+//! Every loop is bounded by [`MAX_LOOP_ITERATIONS`], and the script as a whole by the step and time
+//! limits it's given, so that a script can't stall the server. Copying or building a list costs a
+//! step for every value in it, so a script also can't use up the server's memory by repeatedly
+//! doubling a list.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+use super::ast::{BinaryOp, Builtin, Expr, ExprKind, Stmt, UnaryOp};
+use super::Pos;
+use crate::code::{gear_from, CallLimits, CodeError, ExecEnvironment, Memory, Output, Stored};
+use crate::sim::strict;
+
+/// The maximum number of times the body of any single loop may run in one execution
+const MAX_LOOP_ITERATIONS: usize = 10_000;
+/// The number of steps between each check of the time limit. Checking the clock is relatively
+/// expensive, so we don't do it on every step
+const STEPS_PER_TIME_CHECK: u64 = 1024;
+
+/// A runtime value within a script
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Num(f32),
+    Bool(bool),
+    List(Vec<Value>),
+    /// A fixed set of named fields. These can only come from the environment; scripts have no way
+    /// to construct them
+    Record(Vec<(&'static str, Value)>),
+}
+
+impl Value {
+    /// The number of steps that it takes to copy the value: one for each number or boolean, and
+    /// one for each list or record
+    fn cells(&self) -> u64 {
+        match self {
+            Self::Num(_) | Self::Bool(_) => 1,
+            Self::List(items) => 1 + items.iter().map(Value::cells).sum::<u64>(),
+            Self::Record(fields) => 1 + fields.iter().map(|(_, v)| v.cells()).sum::<u64>(),
+        }
+    }
+
+    fn type_name(&self) -> &'static str {
+        match self {
+            Self::Num(_) => "number",
+            Self::Bool(_) => "boolean",
+            Self::List(_) => "list",
+            Self::Record(_) => "record",
+        }
+    }
+
+    fn from_stored(stored: &Stored) -> Value {
+        match stored {
+            Stored::Num(n) => Value::Num(*n),
+            Stored::Bool(b) => Value::Bool(*b),
+            Stored::List(items) => Value::List(items.iter().map(Value::from_stored).collect()),
+        }
+    }
+
+    /// Converts the value into something that can be kept in memory. Records can't be stored,
+    /// since they only come from the environment
+    fn to_stored(&self) -> Option<Stored> {
+        match self {
+            Self::Num(n) => Some(Stored::Num(*n)),
+            Self::Bool(b) => Some(Stored::Bool(*b)),
+            Self::List(items) => items
+                .iter()
+                .map(Value::to_stored)
+                .collect::<Option<_>>()
+                .map(Stored::List),
+            Self::Record(_) => None,
+        }
+    }
+}
+
+/// Runs the body of a script to completion, extracting the output from its variables
+pub fn run(
+    body: &[Stmt],
+    env: &ExecEnvironment,
+    limits: &CallLimits,
+    memory: &mut Memory,
+) -> Result<Output, CodeError> {
+    let mut interp = Interpreter {
+        vars: HashMap::new(),
+        steps: 0,
+        limits: *limits,
+        memory,
+        persisted: Vec::new(),
+    };
+
+    let car = env.car_currently;
+    interp.vars.insert(
+        "car".to_owned(),
+        Value::Record(vec![
+            ("x", Value::Num(car.pos.x)),
+            ("y", Value::Num(car.pos.y)),
+            ("angle", Value::Num(car.angle.radians())),
+            ("speed", Value::Num(car.speed)),
+            ("slip", Value::Num(car.slip.radians())),
+            ("gear", Value::Num(car.gear as f32)),
+            ("rpm", Value::Num(car.rpm)),
+            ("max_speed", Value::Num(car.max_speed)),
+            ("max_turn", Value::Num(car.max_turn.degrees())),
+        ]),
+    );
+    interp.vars.insert(
+        "dist_to_wall".to_owned(),
+        Value::List(env.dist_to_wall.iter().map(|d| Value::Num(*d)).collect()),
+    );
+    interp.vars.insert(
+        "env".to_owned(),
+        Value::Record(vec![
+            ("tick", Value::Num(env.tick as f32)),
+            ("laps_remaining", Value::Num(env.laps_remaining as f32)),
+            ("dist_to_finish", Value::Num(env.dist_to_finish)),
+            ("bearing_to_finish", Value::Num(env.bearing_to_finish)),
+            (
+                "velocity",
+                Value::Record(vec![
+                    ("x", Value::Num(env.velocity.x)),
+                    ("y", Value::Num(env.velocity.y)),
+                ]),
+            ),
+            (
+                "previous",
+                Value::Record(vec![
+                    ("acc", Value::Num(env.previous_output.acc)),
+                    (
+                        "turning_speed",
+                        Value::Num(env.previous_output.turning_speed),
+                    ),
+                    ("gear", Value::Num(env.previous_output.gear as f32)),
+                ]),
+            ),
+            (
+                "time_remaining",
+                Value::Num(env.time_remaining.as_secs_f32()),
+            ),
+        ]),
+    );
+    interp.vars.insert("pi".to_owned(), Value::Num(PI));
+
+    interp.block(body)?;
+    interp.write_back()?;
+
+    Ok(Output {
+        acc: interp.output_var("acc")?,
+        turning_speed: interp.output_var("turning_speed")?,
+        gear: gear_from(interp.output_var("gear")? as f64)
+            .map_err(|msg| CodeError::Runtime(format!("runtime error: {}", msg)))?,
+    })
+}
+
+struct Interpreter<'m> {
+    vars: HashMap<String, Value>,
+    steps: u64,
+    limits: CallLimits,
+    memory: &'m mut Memory,
+    /// The variables that have been `persist`ed so far, with the position they were first
+    /// persisted at
+    persisted: Vec<(String, Pos)>,
+}
+
+fn error<T>(pos: Pos, msg: impl Into<String>) -> Result<T, CodeError> {
+    Err(CodeError::Runtime(format!(
+        "runtime error at {}: {}",
+        pos,
+        msg.into()
+    )))
+}
+
+impl Interpreter<'_> {
+    /// Counts a single step of evaluation, failing if the script has gone on for too long
+    fn step(&mut self, pos: Pos) -> Result<(), CodeError> {
+        self.charge(pos, 1)
+    }
+
+    /// Counts `steps` steps of evaluation at once, failing if the script has gone on for too long
+    fn charge(&mut self, pos: Pos, steps: u64) -> Result<(), CodeError> {
+        let before = self.steps;
+        self.steps += steps;
+
+        if self.steps > self.limits.steps {
+            return Err(CodeError::Timeout(format!(
+                "timeout at {}: script exceeded {} steps",
+                pos, self.limits.steps
+            )));
+        }
+
+        let checked_time = self.steps / STEPS_PER_TIME_CHECK != before / STEPS_PER_TIME_CHECK;
+        if checked_time && self.limits.time_remaining().as_nanos() == 0 {
+            return Err(CodeError::Timeout(format!(
+                "timeout at {}: script ran out of time",
+                pos
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Reads one of the output variables once the script has finished. Unset outputs default to
+    /// zero
+    fn output_var(&self, name: &str) -> Result<f32, CodeError> {
+        match self.vars.get(name) {
+            None => Ok(0.0),
+            Some(Value::Num(n)) if n.is_finite() => Ok(*n),
+            Some(Value::Num(n)) => Err(CodeError::Runtime(format!(
+                "runtime error: '{}' must be finite, was {}",
+                name, n
+            ))),
+            Some(v) => Err(CodeError::Runtime(format!(
+                "runtime error: '{}' must be a number, was a {}",
+                name,
+                v.type_name()
+            ))),
+        }
+    }
+
+    /// Stores the final value of every persisted variable back into memory
+    fn write_back(&mut self) -> Result<(), CodeError> {
+        for (name, pos) in &self.persisted {
+            let value = &self.vars[name];
+            let stored = match value.to_stored() {
+                Some(s) => s,
+                None => return error(*pos, format!("cannot persist a {}", value.type_name())),
+            };
+            if let Err(msg) = self.memory.set(name, stored) {
+                return error(*pos, msg);
+            }
+        }
+        Ok(())
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) -> Result<(), CodeError> {
+        for s in stmts {
+            self.stmt(s)?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), CodeError> {
+        match stmt {
+            Stmt::Assign { name, value } => {
+                let v = self.expr(value)?;
+                self.vars.insert(name.clone(), v);
+            }
+            Stmt::Persist { name, init, pos } => {
+                if self.persisted.iter().any(|(n, _)| n == name) {
+                    return Ok(());
+                }
+
+                let v = match self.memory.get(name) {
+                    Some(stored) => Value::from_stored(stored),
+                    None => self.expr(init)?,
+                };
+                self.vars.insert(name.clone(), v);
+                self.persisted.push((name.clone(), *pos));
+            }
+            Stmt::If {
+                branches,
+                otherwise,
+            } => {
+                for (cond, body) in branches {
+                    if self.bool_expr(cond)? {
+                        return self.block(body);
+                    }
+                }
+                self.block(otherwise)?;
+            }
+            Stmt::While { cond, body, pos } => {
+                let mut iterations = 0;
+                while self.bool_expr(cond)? {
+                    iterations += 1;
+                    if iterations > MAX_LOOP_ITERATIONS {
+                        return error(
+                            *pos,
+                            format!("loop exceeded {} iterations", MAX_LOOP_ITERATIONS),
+                        );
+                    }
+                    self.block(body)?;
+                }
+            }
+            Stmt::For {
+                var,
+                start,
+                end,
+                body,
+                pos,
+            } => {
+                let start = self.num_expr(start)?.floor();
+                let end = self.num_expr(end)?.floor();
+                if !start.is_finite() || !end.is_finite() {
+                    return error(
+                        *pos,
+                        format!("loop bounds must be finite, were {} and {}", start, end),
+                    );
+                }
+
+                // The bounds can be far too big for their difference to fit in an integer, so the
+                // number of iterations is worked out before converting anything
+                let iterations = (f64::from(end) - f64::from(start)).max(0.0);
+                if iterations > MAX_LOOP_ITERATIONS as f64 {
+                    return error(
+                        *pos,
+                        format!("loop exceeded {} iterations", MAX_LOOP_ITERATIONS),
+                    );
+                }
+
+                for i in 0..iterations as u32 {
+                    // Every iteration counts as a step, even if the body is empty
+                    self.step(*pos)?;
+                    let value = f64::from(start) + f64::from(i);
+                    self.vars.insert(var.clone(), Value::Num(value as f32));
+                    self.block(body)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn bool_expr(&mut self, expr: &Expr) -> Result<bool, CodeError> {
+        let value = self.expr(expr)?;
+        expect_bool(value, expr.pos)
+    }
+
+    fn num_expr(&mut self, expr: &Expr) -> Result<f32, CodeError> {
+        match self.expr(expr)? {
+            Value::Num(n) => Ok(n),
+            v => error(
+                expr.pos,
+                format!("expected a number, found a {}", v.type_name()),
+            ),
+        }
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<Value, CodeError> {
+        self.step(expr.pos)?;
+
+        match &expr.kind {
+            ExprKind::Num(n) => Ok(Value::Num(*n)),
+            ExprKind::Bool(b) => Ok(Value::Bool(*b)),
+            ExprKind::Var(name) => match self.vars.get(name) {
+                Some(v) => {
+                    // The step for the expression itself already covers one cell
+                    let cells = v.cells();
+                    self.charge(expr.pos, cells - 1)?;
+                    Ok(self.vars[name].clone())
+                }
+                None => error(expr.pos, format!("undefined variable '{}'", name)),
+            },
+            // The items have already been paid for as they were evaluated, so building the list
+            // only costs the step for the expression
+            ExprKind::List(items) => {
+                let mut values = Vec::with_capacity(items.len());
+                for item in items {
+                    values.push(self.expr(item)?);
+                }
+                Ok(Value::List(values))
+            }
+            ExprKind::Unary(UnaryOp::Neg, inner) => Ok(Value::Num(-self.num_expr(inner)?)),
+            ExprKind::Unary(UnaryOp::Not, inner) => Ok(Value::Bool(!self.bool_expr(inner)?)),
+            ExprKind::Binary(op, lhs, rhs) => {
+                let lhs = self.expr(lhs)?;
+                let rhs = self.expr(rhs)?;
+                binary_op(*op, lhs, rhs, expr.pos)
+            }
+            ExprKind::Chain(first, links) => {
+                // Each operator costs a step, just like it would if they were nested. The step for
+                // the expression itself already covers the first one
+                self.charge(expr.pos, links.len() as u64 - 1)?;
+
+                let mut value = self.expr(first)?;
+                let mut lhs_pos = first.pos;
+                for (op, pos, rhs) in links {
+                    value = match op {
+                        // `and` and `or` short-circuit, so they're handled separately from the
+                        // other operators
+                        BinaryOp::And => {
+                            Value::Bool(expect_bool(value, lhs_pos)? && self.bool_expr(rhs)?)
+                        }
+                        BinaryOp::Or => {
+                            Value::Bool(expect_bool(value, lhs_pos)? || self.bool_expr(rhs)?)
+                        }
+                        _ => {
+                            let rhs = self.expr(rhs)?;
+                            binary_op(*op, value, rhs, *pos)?
+                        }
+                    };
+                    lhs_pos = *pos;
+                }
+                Ok(value)
+            }
+            ExprKind::Call(builtin, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for a in args {
+                    values.push(self.expr(a)?);
+                }
+                call_builtin(*builtin, values, expr.pos)
+            }
+            ExprKind::Index(list, index) => {
+                // Indexing straight into a variable only has to copy the item, not the whole list
+                if let ExprKind::Var(name) = &list.kind {
+                    if self.vars.contains_key(name) {
+                        self.step(list.pos)?;
+                        let index = self.num_expr(index)?;
+                        let item = index_into(&self.vars[name], index, expr.pos)?;
+                        self.charge(expr.pos, item.cells())?;
+                        return Ok(item);
+                    }
+                }
+
+                let list = self.expr(list)?;
+                let index = self.num_expr(index)?;
+                index_into(&list, index, expr.pos)
+            }
+            ExprKind::Field(record, field) => match self.expr(record)? {
+                Value::Record(fields) => match fields.into_iter().find(|(f, _)| f == field) {
+                    Some((_, v)) => Ok(v),
+                    None => error(expr.pos, format!("no field named '{}'", field)),
+                },
+                v => error(expr.pos, format!("a {} has no fields", v.type_name())),
+            },
+        }
+    }
+}
+
+fn expect_bool(value: Value, pos: Pos) -> Result<bool, CodeError> {
+    match value {
+        Value::Bool(b) => Ok(b),
+        v => error(
+            pos,
+            format!("expected a boolean, found a {}", v.type_name()),
+        ),
+    }
+}
+
+fn index_into(list: &Value, index: f32, pos: Pos) -> Result<Value, CodeError> {
+    match list {
+        Value::List(items) => {
+            if index < 0.0 || index.fract() != 0.0 || index as usize >= items.len() {
+                return error(
+                    pos,
+                    format!(
+                        "index {} out of range for list of length {}",
+                        index,
+                        items.len()
+                    ),
+                );
+            }
+            Ok(items[index as usize].clone())
+        }
+        v => error(pos, format!("cannot index into a {}", v.type_name())),
+    }
+}
+
+fn binary_op(op: BinaryOp, lhs: Value, rhs: Value, pos: Pos) -> Result<Value, CodeError> {
+    use BinaryOp::*;
+
+    match (op, lhs, rhs) {
+        (Eq, l, r) => Ok(Value::Bool(l == r)),
+        (NotEq, l, r) => Ok(Value::Bool(l != r)),
+        (_, Value::Num(l), Value::Num(r)) => {
+            let v = match op {
+                Add => Value::Num(l + r),
+                Sub => Value::Num(l - r),
+                Mul => Value::Num(l * r),
+                Div | Rem if r == 0.0 => return error(pos, "division by zero"),
+                Div => Value::Num(l / r),
+                Rem => Value::Num(l % r),
+                Lt => Value::Bool(l < r),
+                LtEq => Value::Bool(l <= r),
+                Gt => Value::Bool(l > r),
+                GtEq => Value::Bool(l >= r),
+                // Handled above
+                Eq | NotEq | And | Or => unreachable!(),
+            };
+            Ok(v)
+        }
+        (_, l, r) => error(
+            pos,
+            format!(
+                "'{}' is not defined for a {} and a {}",
+                op.symbol(),
+                l.type_name(),
+                r.type_name()
+            ),
+        ),
+    }
+}
+
+fn call_builtin(builtin: Builtin, args: Vec<Value>, pos: Pos) -> Result<Value, CodeError> {
+    if let Builtin::Len = builtin {
+        return match &args[0] {
+            Value::List(items) => Ok(Value::Num(items.len() as f32)),
+            v => error(
+                pos,
+                format!("len expects a list, found a {}", v.type_name()),
+            ),
+        };
+    }
+
+    // All of the other builtins only take numbers
+    let mut nums = Vec::with_capacity(args.len());
+    for a in args {
+        match a {
+            Value::Num(n) => nums.push(n),
+            v => {
+                return error(
+                    pos,
+                    format!(
+                        "{} expects numbers, found a {}",
+                        builtin.name(),
+                        v.type_name()
+                    ),
+                )
+            }
+        }
+    }
+
+    let n = match builtin {
+        Builtin::Abs => nums[0].abs(),
+        Builtin::Min => nums[0].min(nums[1]),
+        Builtin::Max => nums[0].max(nums[1]),
+        Builtin::Clamp => nums[0].max(nums[1]).min(nums[2]),
+        Builtin::Sqrt => nums[0].sqrt(),
+        // These use the simulation's own versions, so that scripts are as reproducible as the
+        // rest of the race
+        Builtin::Sin => strict::sin(nums[0]),
+        Builtin::Cos => strict::cos(nums[0]),
+        Builtin::Tan => strict::tan(nums[0]),
+        Builtin::Atan2 => strict::atan2(nums[0], nums[1]),
+        Builtin::Floor => nums[0].floor(),
+        Builtin::Ceil => nums[0].ceil(),
+        Builtin::Round => nums[0].round(),
+        Builtin::Len => unreachable!(),
+    };
+
+    Ok(Value::Num(n))
+}
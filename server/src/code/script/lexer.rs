@@ -0,0 +1,242 @@
+//! Tokenizer for driving scripts
+
+use super::Pos;
+
+/// The different kinds of token that can appear in a script
+#[derive(Clone, Debug, PartialEq)]
+pub enum TokenKind {
+    Num(f32),
+    Ident(String),
+
+    // Keywords
+    If,
+    Else,
+    While,
+    For,
+    In,
+    And,
+    Or,
+    Not,
+    True,
+    False,
+    Persist,
+
+    // Punctuation
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    LBrace,
+    RBrace,
+    Comma,
+    Dot,
+    DotDot,
+    Semi,
+    Assign,
+    Eq,
+    NotEq,
+    Lt,
+    LtEq,
+    Gt,
+    GtEq,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+
+    /// Always the last token produced by [`tokenize`]
+    Eof,
+}
+
+impl TokenKind {
+    /// A human-readable description of the token, for use in error messages
+    pub fn describe(&self) -> String {
+        let s = match self {
+            Self::Num(n) => return format!("number {}", n),
+            Self::Ident(name) => return format!("name '{}'", name),
+            Self::Eof => return "end of input".to_owned(),
+            Self::If => "if",
+            Self::Else => "else",
+            Self::While => "while",
+            Self::For => "for",
+            Self::In => "in",
+            Self::And => "and",
+            Self::Or => "or",
+            Self::Not => "not",
+            Self::True => "true",
+            Self::False => "false",
+            Self::Persist => "persist",
+            Self::LParen => "(",
+            Self::RParen => ")",
+            Self::LBracket => "[",
+            Self::RBracket => "]",
+            Self::LBrace => "{",
+            Self::RBrace => "}",
+            Self::Comma => ",",
+            Self::Dot => ".",
+            Self::DotDot => "..",
+            Self::Semi => ";",
+            Self::Assign => "=",
+            Self::Eq => "==",
+            Self::NotEq => "!=",
+            Self::Lt => "<",
+            Self::LtEq => "<=",
+            Self::Gt => ">",
+            Self::GtEq => ">=",
+            Self::Plus => "+",
+            Self::Minus => "-",
+            Self::Star => "*",
+            Self::Slash => "/",
+            Self::Percent => "%",
+        };
+
+        format!("'{}'", s)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub pos: Pos,
+}
+
+/// Splits the input into tokens, returning an error for any character that can't start a token
+///
+/// Comments start with a '#' and continue until the end of the line; they're dropped here, along
+/// with all whitespace.
+pub fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+
+    let mut i = 0;
+    let mut pos = Pos { line: 1, col: 1 };
+
+    // Moves forward by `n` characters, keeping track of the position as we go
+    let advance = |i: &mut usize, pos: &mut Pos, n: usize| {
+        for _ in 0..n {
+            if chars[*i] == '\n' {
+                pos.line += 1;
+                pos.col = 1;
+            } else {
+                pos.col += 1;
+            }
+            *i += 1;
+        }
+    };
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+        let start = pos;
+
+        if c.is_whitespace() {
+            advance(&mut i, &mut pos, 1);
+            continue;
+        }
+
+        if c == '#' {
+            while i < chars.len() && chars[i] != '\n' {
+                advance(&mut i, &mut pos, 1);
+            }
+            continue;
+        }
+
+        if c.is_ascii_digit() {
+            let mut len = 0;
+            while matches!(chars.get(i + len), Some(c) if c.is_ascii_digit()) {
+                len += 1;
+            }
+
+            // A '.' is only part of the number if it isn't the start of a range (`..`)
+            if chars.get(i + len) == Some(&'.') && chars.get(i + len + 1) != Some(&'.') {
+                len += 1;
+                while matches!(chars.get(i + len), Some(c) if c.is_ascii_digit()) {
+                    len += 1;
+                }
+            }
+
+            let text: String = chars[i..i + len].iter().collect();
+            let value = text
+                .parse()
+                .map_err(|_| format!("parse error at {}: invalid number '{}'", start, text))?;
+
+            tokens.push(Token {
+                kind: TokenKind::Num(value),
+                pos: start,
+            });
+            advance(&mut i, &mut pos, len);
+            continue;
+        }
+
+        if c.is_alphabetic() || c == '_' {
+            let mut len = 0;
+            while matches!(chars.get(i + len), Some(c) if c.is_alphanumeric() || *c == '_') {
+                len += 1;
+            }
+
+            let text: String = chars[i..i + len].iter().collect();
+            let kind = match text.as_str() {
+                "if" => TokenKind::If,
+                "else" => TokenKind::Else,
+                "while" => TokenKind::While,
+                "for" => TokenKind::For,
+                "in" => TokenKind::In,
+                "and" => TokenKind::And,
+                "or" => TokenKind::Or,
+                "not" => TokenKind::Not,
+                "true" => TokenKind::True,
+                "false" => TokenKind::False,
+                "persist" => TokenKind::Persist,
+                _ => TokenKind::Ident(text),
+            };
+
+            tokens.push(Token { kind, pos: start });
+            advance(&mut i, &mut pos, len);
+            continue;
+        }
+
+        // Everything else is punctuation. We check the two-character tokens first, so that (for
+        // example) "<=" isn't read as "<" followed by "=".
+        let (kind, len) = match (c, next) {
+            ('.', Some('.')) => (TokenKind::DotDot, 2),
+            ('=', Some('=')) => (TokenKind::Eq, 2),
+            ('!', Some('=')) => (TokenKind::NotEq, 2),
+            ('<', Some('=')) => (TokenKind::LtEq, 2),
+            ('>', Some('=')) => (TokenKind::GtEq, 2),
+            ('(', _) => (TokenKind::LParen, 1),
+            (')', _) => (TokenKind::RParen, 1),
+            ('[', _) => (TokenKind::LBracket, 1),
+            (']', _) => (TokenKind::RBracket, 1),
+            ('{', _) => (TokenKind::LBrace, 1),
+            ('}', _) => (TokenKind::RBrace, 1),
+            (',', _) => (TokenKind::Comma, 1),
+            ('.', _) => (TokenKind::Dot, 1),
+            (';', _) => (TokenKind::Semi, 1),
+            ('=', _) => (TokenKind::Assign, 1),
+            ('<', _) => (TokenKind::Lt, 1),
+            ('>', _) => (TokenKind::Gt, 1),
+            ('+', _) => (TokenKind::Plus, 1),
+            ('-', _) => (TokenKind::Minus, 1),
+            ('*', _) => (TokenKind::Star, 1),
+            ('/', _) => (TokenKind::Slash, 1),
+            ('%', _) => (TokenKind::Percent, 1),
+            _ => {
+                return Err(format!(
+                    "parse error at {}: unexpected character {:?}",
+                    start, c
+                ))
+            }
+        };
+
+        tokens.push(Token { kind, pos: start });
+        advance(&mut i, &mut pos, len);
+    }
+
+    tokens.push(Token {
+        kind: TokenKind::Eof,
+        pos,
+    });
+
+    Ok(tokens)
+}
@@ -0,0 +1,56 @@
+//! The driving-script language
+//!
+//! This is a deliberately small language: there's numbers, booleans and lists, variables (which can
+//! be `persist`ed between decisions), `if`/`else`, `while` and `for` loops, and a fixed set of math
+//! builtins. There's no way to define functions or to touch anything outside of the values we hand
+//! to the script, which keeps it sandboxed without any extra work. The full description of the
+//! language is in `Docs/Scripting.md`.
+//!
+//! Scripts are processed in the usual stages: [`lexer`] turns the source into tokens, [`parser`]
+//! builds the [AST](ast), and [`eval`] runs the AST against an [`ExecEnvironment`].
+
+use std::fmt::{self, Display, Formatter};
+
+use super::{CallLimits, CodeError, ExecEnvironment, Memory, Output};
+
+mod ast;
+mod eval;
+mod lexer;
+mod parser;
+
+/// A position in the source of a script, used for error messages. Both fields are indexed from 1
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Pos {
+    pub line: usize,
+    pub col: usize,
+}
+
+impl Display for Pos {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.col)
+    }
+}
+
+/// A parsed driving script, ready to be executed
+pub struct Program {
+    body: Vec<ast::Stmt>,
+}
+
+impl Program {
+    /// Parses the script, returning a parse error (with its line and column) if there was one
+    pub fn parse(input: &str) -> Result<Program, String> {
+        let tokens = lexer::tokenize(input)?;
+        let body = parser::parse(tokens)?;
+        Ok(Program { body })
+    }
+
+    /// Runs the script once, producing the car's directions for the given environment
+    pub fn run(
+        &self,
+        env: &ExecEnvironment,
+        limits: &CallLimits,
+        memory: &mut Memory,
+    ) -> Result<Output, CodeError> {
+        eval::run(&self.body, env, limits, memory)
+    }
+}
@@ -0,0 +1,452 @@
+//! Recursive-descent parser for driving scripts
+//!
+//! The grammar is roughly:
+//! ```text
+//! block   = stmt* ;
+//! stmt    = (assign | persist | if | while | for) ";"? ;
+//! assign  = IDENT "=" expr ;
+//! persist = "persist" IDENT "=" expr ;
+//! if      = "if" expr "{" block "}" ("else" "if" expr "{" block "}")* ("else" "{" block "}")? ;
+//! while   = "while" expr "{" block "}" ;
+//! for     = "for" IDENT "in" expr ".." expr "{" block "}" ;
+//!
+//! expr    = or ;
+//! or      = and ("or" and)* ;
+//! and     = not ("and" not)* ;
+//! not     = "not" not | compare ;
+//! compare = sum (("==" | "!=" | "<" | "<=" | ">" | ">=") sum)? ;
+//! sum     = product (("+" | "-") product)* ;
+//! product = unary (("*" | "/" | "%") unary)* ;
+//! unary   = "-" unary | postfix ;
+//! postfix = primary ("[" expr "]" | "." IDENT)* ;
+//! primary = NUMBER | "true" | "false" | IDENT | IDENT "(" args ")" | "(" expr ")" | "[" args "]" ;
+//! ```
+
+use super::ast::{BinaryOp, Builtin, Expr, ExprKind, Stmt, UnaryOp};
+use super::lexer::{Token, TokenKind};
+use super::Pos;
+
+/// How deeply expressions and blocks can be nested. Both the parser and the evaluator recurse
+/// through every level, so without a limit a script could overflow the stack and take down the
+/// whole server
+const MAX_NESTING: usize = 128;
+
+/// Parses the full list of tokens into the statements making up the script
+pub fn parse(tokens: Vec<Token>) -> Result<Vec<Stmt>, String> {
+    let mut parser = Parser {
+        tokens,
+        idx: 0,
+        depth: 0,
+    };
+    let body = parser.block()?;
+
+    match parser.peek() {
+        TokenKind::Eof => Ok(body),
+        _ => Err(parser.unexpected("a statement")),
+    }
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    idx: usize,
+    /// How many levels of nesting we're currently inside of
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> &TokenKind {
+        &self.tokens[self.idx].kind
+    }
+
+    fn pos(&self) -> Pos {
+        self.tokens[self.idx].pos
+    }
+
+    fn next(&mut self) -> Token {
+        let tok = self.tokens[self.idx].clone();
+        // We never move past the final `Eof` token
+        if self.idx + 1 < self.tokens.len() {
+            self.idx += 1;
+        }
+        tok
+    }
+
+    /// Consumes the next token if it's `kind`, returning whether it was
+    fn eat(&mut self, kind: &TokenKind) -> bool {
+        if self.peek() == kind {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, kind: &TokenKind) -> Result<(), String> {
+        if self.eat(kind) {
+            Ok(())
+        } else {
+            Err(self.unexpected(&kind.describe()))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String, String> {
+        match self.peek().clone() {
+            TokenKind::Ident(name) => {
+                self.next();
+                Ok(name)
+            }
+            _ => Err(self.unexpected("a name")),
+        }
+    }
+
+    /// Produces the error for when we find something other than what we were `expecting`
+    fn unexpected(&self, expecting: &str) -> String {
+        format!(
+            "parse error at {}: expected {}, found {}",
+            self.pos(),
+            expecting,
+            self.peek().describe()
+        )
+    }
+
+    /// Parses something one level of nesting deeper, failing if that's too deep
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        self.deeper(1)?;
+        let parsed = parse(self)?;
+        self.depth -= 1;
+        Ok(parsed)
+    }
+
+    /// Goes `levels` levels of nesting deeper, failing if that's too deep
+    fn deeper(&mut self, levels: usize) -> Result<(), String> {
+        self.depth += levels;
+        if self.depth > MAX_NESTING {
+            return Err(format!(
+                "parse error at {}: the script is nested more than {} levels deep",
+                self.pos(),
+                MAX_NESTING
+            ));
+        }
+        Ok(())
+    }
+
+    /// Parses statements until the end of the enclosing block (or input)
+    fn block(&mut self) -> Result<Vec<Stmt>, String> {
+        let mut stmts = Vec::new();
+        while !matches!(self.peek(), TokenKind::RBrace | TokenKind::Eof) {
+            stmts.push(self.stmt()?);
+            self.eat(&TokenKind::Semi);
+        }
+        Ok(stmts)
+    }
+
+    fn braced_block(&mut self) -> Result<Vec<Stmt>, String> {
+        self.expect(&TokenKind::LBrace)?;
+        let body = self.nested(Self::block)?;
+        self.expect(&TokenKind::RBrace)?;
+        Ok(body)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt, String> {
+        let pos = self.pos();
+
+        match self.peek() {
+            TokenKind::If => {
+                self.next();
+                let mut branches = vec![(self.expr()?, self.braced_block()?)];
+                let mut otherwise = Vec::new();
+
+                while self.eat(&TokenKind::Else) {
+                    if self.eat(&TokenKind::If) {
+                        branches.push((self.expr()?, self.braced_block()?));
+                    } else {
+                        otherwise = self.braced_block()?;
+                        break;
+                    }
+                }
+
+                Ok(Stmt::If {
+                    branches,
+                    otherwise,
+                })
+            }
+            TokenKind::While => {
+                self.next();
+                let cond = self.expr()?;
+                let body = self.braced_block()?;
+                Ok(Stmt::While { cond, body, pos })
+            }
+            TokenKind::For => {
+                self.next();
+                let var = self.expect_ident()?;
+                self.expect(&TokenKind::In)?;
+                let start = self.expr()?;
+                self.expect(&TokenKind::DotDot)?;
+                let end = self.expr()?;
+                let body = self.braced_block()?;
+                Ok(Stmt::For {
+                    var,
+                    start,
+                    end,
+                    body,
+                    pos,
+                })
+            }
+            TokenKind::Persist => {
+                self.next();
+                let name = self.expect_ident()?;
+                self.expect(&TokenKind::Assign)?;
+                let init = self.expr()?;
+                Ok(Stmt::Persist { name, init, pos })
+            }
+            TokenKind::Ident(_) => {
+                let name = self.expect_ident()?;
+                self.expect(&TokenKind::Assign)?;
+                let value = self.expr()?;
+                Ok(Stmt::Assign { name, value })
+            }
+            _ => Err(self.unexpected("a statement")),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, String> {
+        self.nested(Self::or)
+    }
+
+    /// Parses a left-associative chain of binary operators, with `operand` for each side
+    ///
+    /// The chain is parsed in a loop and kept flat, so however long it is, it doesn't count
+    /// towards the nesting limit.
+    fn binary_chain(
+        &mut self,
+        ops: &[(TokenKind, BinaryOp)],
+        operand: fn(&mut Self) -> Result<Expr, String>,
+    ) -> Result<Expr, String> {
+        let first = operand(self)?;
+        let mut links = Vec::new();
+
+        'chain: loop {
+            for (tok, op) in ops {
+                if self.peek() == tok {
+                    let pos = self.next().pos;
+                    links.push((*op, pos, operand(self)?));
+                    continue 'chain;
+                }
+            }
+
+            break;
+        }
+
+        match links.first() {
+            Some(&(_, pos, _)) => Ok(Expr {
+                kind: ExprKind::Chain(Box::new(first), links),
+                pos,
+            }),
+            None => Ok(first),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        self.binary_chain(&[(TokenKind::Or, BinaryOp::Or)], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        self.binary_chain(&[(TokenKind::And, BinaryOp::And)], Self::not)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.peek() == &TokenKind::Not {
+            let pos = self.next().pos;
+            let inner = self.nested(Self::not)?;
+            return Ok(Expr {
+                kind: ExprKind::Unary(UnaryOp::Not, Box::new(inner)),
+                pos,
+            });
+        }
+
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Expr, String> {
+        let lhs = self.sum()?;
+
+        let op = match self.peek() {
+            TokenKind::Eq => BinaryOp::Eq,
+            TokenKind::NotEq => BinaryOp::NotEq,
+            TokenKind::Lt => BinaryOp::Lt,
+            TokenKind::LtEq => BinaryOp::LtEq,
+            TokenKind::Gt => BinaryOp::Gt,
+            TokenKind::GtEq => BinaryOp::GtEq,
+            _ => return Ok(lhs),
+        };
+
+        let pos = self.next().pos;
+        let rhs = self.sum()?;
+        Ok(Expr {
+            kind: ExprKind::Binary(op, Box::new(lhs), Box::new(rhs)),
+            pos,
+        })
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        self.binary_chain(
+            &[
+                (TokenKind::Plus, BinaryOp::Add),
+                (TokenKind::Minus, BinaryOp::Sub),
+            ],
+            Self::product,
+        )
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        self.binary_chain(
+            &[
+                (TokenKind::Star, BinaryOp::Mul),
+                (TokenKind::Slash, BinaryOp::Div),
+                (TokenKind::Percent, BinaryOp::Rem),
+            ],
+            Self::unary,
+        )
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == &TokenKind::Minus {
+            let pos = self.next().pos;
+            let inner = self.nested(Self::unary)?;
+            return Ok(Expr {
+                kind: ExprKind::Unary(UnaryOp::Neg, Box::new(inner)),
+                pos,
+            });
+        }
+
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.primary()?;
+        // Each index or field nests the expression another level deeper in the tree
+        let mut length = 0;
+
+        loop {
+            let pos = self.pos();
+            if matches!(self.peek(), TokenKind::LBracket | TokenKind::Dot) {
+                self.deeper(1)?;
+                length += 1;
+            }
+
+            if self.eat(&TokenKind::LBracket) {
+                let index = self.expr()?;
+                self.expect(&TokenKind::RBracket)?;
+                expr = Expr {
+                    kind: ExprKind::Index(Box::new(expr), Box::new(index)),
+                    pos,
+                };
+            } else if self.eat(&TokenKind::Dot) {
+                let field = self.expect_ident()?;
+                expr = Expr {
+                    kind: ExprKind::Field(Box::new(expr), field),
+                    pos,
+                };
+            } else {
+                self.depth -= length;
+                return Ok(expr);
+            }
+        }
+    }
+
+    /// Parses a comma-separated list of expressions, up to (and including) the `close` token
+    fn args(&mut self, close: &TokenKind) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        if self.eat(close) {
+            return Ok(args);
+        }
+
+        loop {
+            args.push(self.expr()?);
+            if self.eat(close) {
+                return Ok(args);
+            }
+            self.expect(&TokenKind::Comma)?;
+        }
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        let pos = self.pos();
+
+        let kind = match self.peek().clone() {
+            TokenKind::Num(n) => {
+                self.next();
+                ExprKind::Num(n)
+            }
+            TokenKind::True => {
+                self.next();
+                ExprKind::Bool(true)
+            }
+            TokenKind::False => {
+                self.next();
+                ExprKind::Bool(false)
+            }
+            TokenKind::Ident(name) => {
+                self.next();
+                if !self.eat(&TokenKind::LParen) {
+                    ExprKind::Var(name)
+                } else {
+                    let builtin = Builtin::from_name(&name).ok_or_else(|| {
+                        format!("parse error at {}: unknown function '{}'", pos, name)
+                    })?;
+                    let args = self.args(&TokenKind::RParen)?;
+                    if args.len() != builtin.arity() {
+                        return Err(format!(
+                            "parse error at {}: '{}' takes {} argument(s), found {}",
+                            pos,
+                            name,
+                            builtin.arity(),
+                            args.len()
+                        ));
+                    }
+                    ExprKind::Call(builtin, args)
+                }
+            }
+            TokenKind::LParen => {
+                self.next();
+                let inner = self.expr()?;
+                self.expect(&TokenKind::RParen)?;
+                return Ok(inner);
+            }
+            TokenKind::LBracket => {
+                self.next();
+                ExprKind::List(self.args(&TokenKind::RBracket)?)
+            }
+            _ => return Err(self.unexpected("an expression")),
+        };
+
+        Ok(Expr { kind, pos })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::lexer::tokenize;
+    use super::*;
+
+    fn parse_str(source: &str) -> Result<Vec<Stmt>, String> {
+        parse(tokenize(source)?)
+    }
+
+    #[test]
+    fn long_chains_are_not_nested() {
+        let terms = vec!["0"; 100_000];
+        assert!(parse_str(&format!("acc = {}", terms.join(" + "))).is_ok());
+        assert!(parse_str(&format!("ok = {}", vec!["true"; 100_000].join(" and "))).is_ok());
+    }
+
+    #[test]
+    fn brackets_are_nested() {
+        let source = format!("acc = {}0{}", "(".repeat(200), ")".repeat(200));
+        let err = parse_str(&source).unwrap_err();
+        assert!(err.contains("nested more than"), "{}", err);
+    }
+}
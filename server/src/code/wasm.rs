@@ -0,0 +1,354 @@
+//! WebAssembly driver modules, run with the wasmi interpreter
+//!
+//! This lets users write their bots in anything that compiles to WebAssembly. Modules are given a
+//! fixed amount of fuel (roughly, a number of instructions) for each decision and a cap on their
+//! memory, so a runaway module produces an error instead of stalling the server. The amount of fuel
+//! is [`FUEL_PER_STEP`] for each step in the decision's [limits](CallLimits). A module may have at
+//! most one memory of up to [`MEMORY_LIMIT`] bytes and one table of up to [`TABLE_ELEMENTS_LIMIT`]
+//! elements; modules that start out bigger than that are rejected when they're compiled.
+//!
+//! ## ABI
+//!
+//! A module must export:
+//!  * `memory` - its linear memory;
+//!  * `env_buffer(len: i32) -> i32` - returns the address of a buffer of at least `len` bytes,
+//!    which we'll write the environment into; and
+//!  * `drive(ptr: i32, len: i32)` - called with the buffer once the environment is written to it.
+//!
+//! The environment is written as a packed sequence of little-endian values:
+//! ```text
+//!  offset | type | value
+//! --------+------+-----------------------------
+//!       0 | f32  | car.pos.x
+//!       4 | f32  | car.pos.y
+//!       8 | f32  | car.angle
+//!      12 | f32  | car.speed
+//!      16 | f32  | car.slip
+//!      20 | f32  | car.max_speed
+//!      24 | f32  | car.max_turn
+//!      28 | i32  | car.gear
+//!      32 | f32  | car.rpm
+//!      36 | i32  | tick
+//!      40 | i32  | laps_remaining
+//!      44 | f32  | dist_to_finish
+//!      48 | f32  | bearing_to_finish
+//!      52 | f32  | velocity.x
+//!      56 | f32  | velocity.y
+//!      60 | f32  | previous_output.acc
+//!      64 | f32  | previous_output.turning_speed
+//!      68 | i32  | previous_output.gear
+//!      72 | f32  | time_remaining, in seconds
+//!      76 | u32  | n, the number of distances
+//!      80 | f32  | dist_to_wall[0]
+//!     ... | ...  | ...
+//! 80+4(n-1)| f32 | dist_to_wall[n - 1]
+//! ```
+//!
+//! To give its directions, `drive` calls the imported function `env.set_output(acc: f32,
+//! turning_speed: f32)`. If it's never called, the output is zero for both. The gear is chosen
+//! separately with `env.set_gear(gear: i32)`, and is left to the gearbox (0) if that isn't called.
+//!
+//! Since every decision gets a fresh instance, a module's own memory doesn't last between them.
+//! Instead, it can keep numbers in the race's [`Memory`] through numbered slots, with the imports
+//! `env.mem_get(slot: i32) -> f32` (which gives 0 for a slot that was never set) and
+//! `env.mem_set(slot: i32, value: f32)`.
+//!
+//! No other imports are available.
+
+use wasmi::core::{Trap, TrapCode, ValueType, F32};
+use wasmi::{
+    Caller, Config, Engine, ExternType, Linker, Module as WasmModule, Store, StoreLimits,
+    StoreLimitsBuilder,
+};
+
+use super::{gear_from, CallLimits, CodeError, ExecEnvironment, Memory, Output, Stored};
+
+/// The amount of fuel that a module is given for each step it's allowed. Individual wasm
+/// instructions are much cheaper than the steps in our other backends, so this makes the limits
+/// roughly comparable
+const FUEL_PER_STEP: u64 = 10;
+/// The maximum size of a module's linear memory, in bytes
+const MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+/// The maximum number of elements in a module's table
+const TABLE_ELEMENTS_LIMIT: u32 = 10_000;
+
+/// The functions that a module must export, given as `(name, params, results)`
+const REQUIRED_FUNCS: &[(&str, &[ValueType], &[ValueType])] = &[
+    ("env_buffer", &[ValueType::I32], &[ValueType::I32]),
+    ("drive", &[ValueType::I32, ValueType::I32], &[]),
+];
+/// The functions that a module may import from `env`
+const AVAILABLE_IMPORTS: &[&str] = &["set_output", "set_gear", "mem_get", "mem_set"];
+
+/// The state available to the host functions we provide to modules
+struct HostState {
+    limits: StoreLimits,
+    output: Output,
+    memory: Memory,
+}
+
+/// A validated WebAssembly module, ready to be instantiated for each decision
+pub struct Module {
+    engine: Engine,
+    module: WasmModule,
+    linker: Linker<HostState>,
+}
+
+fn wasm_error(err: impl std::fmt::Display) -> String {
+    format!("wasm error: {}", err)
+}
+
+impl Module {
+    /// Compiles the module, checking that it matches the ABI
+    pub fn compile(bytes: &[u8]) -> Result<Module, String> {
+        let mut config = Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let module = WasmModule::new(&engine, bytes).map_err(wasm_error)?;
+
+        let mut has_memory = false;
+        let mut missing: Vec<&str> = REQUIRED_FUNCS.iter().map(|(name, _, _)| *name).collect();
+
+        for export in module.exports() {
+            match (export.name(), export.ty()) {
+                ("memory", ExternType::Memory(_)) => has_memory = true,
+                ("memory", _) => return Err(wasm_error("export 'memory' must be a memory")),
+                (name, ty) => {
+                    let (_, params, results) = match REQUIRED_FUNCS.iter().find(|f| f.0 == name) {
+                        Some(f) => f,
+                        None => continue,
+                    };
+
+                    match ty {
+                        ExternType::Func(f) if f.params() == *params && f.results() == *results => {
+                            missing.retain(|m| *m != name)
+                        }
+                        _ => {
+                            return Err(wasm_error(format!(
+                                "export '{}' must be a function with params {:?} and results {:?}",
+                                name, params, results
+                            )))
+                        }
+                    }
+                }
+            }
+        }
+
+        if !has_memory {
+            return Err(wasm_error("module must export its 'memory'"));
+        }
+        if let Some(name) = missing.first() {
+            return Err(wasm_error(format!("missing exported function '{}'", name)));
+        }
+
+        for import in module.imports() {
+            if import.module() != "env" || !AVAILABLE_IMPORTS.contains(&import.name()) {
+                return Err(wasm_error(format!(
+                    "unknown import '{}.{}'",
+                    import.module(),
+                    import.name()
+                )));
+            }
+        }
+
+        let mut linker = Linker::new(&engine);
+        linker
+            .func_wrap(
+                "env",
+                "set_output",
+                |mut caller: Caller<'_, HostState>, acc: F32, turning_speed: F32| {
+                    let output = &mut caller.data_mut().output;
+                    output.acc = acc.to_float();
+                    output.turning_speed = turning_speed.to_float();
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "env",
+                "set_gear",
+                |mut caller: Caller<'_, HostState>, gear: i32| -> Result<(), Trap> {
+                    caller.data_mut().output.gear = gear_from(gear as f64).map_err(Trap::new)?;
+                    Ok(())
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "env",
+                "mem_get",
+                |caller: Caller<'_, HostState>, slot: i32| -> F32 {
+                    match caller.data().memory.get(&slot.to_string()) {
+                        Some(Stored::Num(n)) => F32::from(*n),
+                        _ => F32::from(0.0),
+                    }
+                },
+            )
+            .map_err(wasm_error)?;
+        linker
+            .func_wrap(
+                "env",
+                "mem_set",
+                |mut caller: Caller<'_, HostState>, slot: i32, value: F32| -> Result<(), Trap> {
+                    if slot < 0 {
+                        return Err(Trap::new(format!("memory slot {} is negative", slot)));
+                    }
+                    caller
+                        .data_mut()
+                        .memory
+                        .set(&slot.to_string(), Stored::Num(value.to_float()))
+                        .map_err(Trap::new)
+                },
+            )
+            .map_err(wasm_error)?;
+
+        let compiled = Module {
+            engine,
+            module,
+            linker,
+        };
+
+        // Tables and memories are allocated when the module is instantiated, so doing that once
+        // here (without running the start function) rejects any that are too big on submission,
+        // rather than part of the way through the race
+        compiled
+            .linker
+            .instantiate(&mut compiled.store(Memory::new(0)), &compiled.module)
+            .map_err(|e| wasm_error(format!("couldn't instantiate the module: {}", e)))?;
+
+        Ok(compiled)
+    }
+
+    /// Makes a store for a single instance of the module, with its memory and table capped
+    fn store(&self, memory: Memory) -> Store<HostState> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(MEMORY_LIMIT)
+            .table_elements(TABLE_ELEMENTS_LIMIT)
+            .instances(1)
+            .memories(1)
+            .tables(1)
+            .build();
+        let state = HostState {
+            limits,
+            output: Output::default(),
+            memory,
+        };
+
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.limits);
+        store
+    }
+
+    /// Instantiates the module and calls its `drive` function for the given environment
+    ///
+    /// Each call gets a fresh instance, so nothing in the module's memory carries over between
+    /// decisions.
+    pub fn run(
+        &self,
+        env: &ExecEnvironment,
+        limits: &CallLimits,
+        memory: &mut Memory,
+    ) -> Result<Output, CodeError> {
+        let mut store = self.store(memory.clone());
+        let fuel = limits.steps.saturating_mul(FUEL_PER_STEP);
+        store
+            .add_fuel(fuel)
+            .map_err(|e| CodeError::Runtime(wasm_error(e)))?;
+
+        let map_err = |trap: Trap| match trap.trap_code() {
+            Some(TrapCode::OutOfFuel) => CodeError::Timeout(wasm_error(format!(
+                "ran out of fuel (the limit is {} per decision)",
+                fuel
+            ))),
+            _ => CodeError::Runtime(wasm_error(trap)),
+        };
+
+        // Instantiation runs the module's start function, if it has one, so this might also fail
+        // because of fuel
+        let instance = self
+            .linker
+            .instantiate(&mut store, &self.module)
+            .and_then(|pre| pre.start(&mut store))
+            .map_err(|err| match err {
+                wasmi::Error::Trap(trap) => map_err(trap),
+                err => CodeError::Runtime(wasm_error(err)),
+            })?;
+
+        // All of these were checked when the module was compiled
+        let linear_memory = instance.get_memory(&store, "memory").unwrap();
+        let env_buffer = instance
+            .get_typed_func::<i32, i32>(&store, "env_buffer")
+            .unwrap();
+        let drive = instance
+            .get_typed_func::<(i32, i32), ()>(&store, "drive")
+            .unwrap();
+
+        let record = encode_env(env);
+        let ptr = env_buffer
+            .call(&mut store, record.len() as i32)
+            .map_err(map_err)?;
+        linear_memory
+            .write(&mut store, ptr as u32 as usize, &record)
+            .map_err(|_| {
+                CodeError::Runtime(wasm_error("'env_buffer' returned an out-of-bounds address"))
+            })?;
+
+        drive
+            .call(&mut store, (ptr, record.len() as i32))
+            .map_err(map_err)?;
+
+        let output = store.data().output;
+        if !output.acc.is_finite() || !output.turning_speed.is_finite() {
+            return Err(CodeError::Runtime(wasm_error(format!(
+                "set_output was given non-finite values ({}, {})",
+                output.acc, output.turning_speed
+            ))));
+        }
+
+        *memory = store.into_data().memory;
+        Ok(output)
+    }
+}
+
+/// Encodes the environment into the layout described in the module-level docs
+fn encode_env(env: &ExecEnvironment) -> Vec<u8> {
+    let car = env.car_currently;
+    let fields = [
+        car.pos.x,
+        car.pos.y,
+        car.angle.radians(),
+        car.speed,
+        car.slip.radians(),
+        car.max_speed,
+        car.max_turn.degrees(),
+    ];
+
+    let mut bytes = Vec::with_capacity(80 + 4 * env.dist_to_wall.len());
+    for f in fields.iter() {
+        bytes.extend_from_slice(&f.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(car.gear as i32).to_le_bytes());
+    bytes.extend_from_slice(&car.rpm.to_le_bytes());
+    bytes.extend_from_slice(&env.tick.to_le_bytes());
+    bytes.extend_from_slice(&env.laps_remaining.to_le_bytes());
+    let fields = [
+        env.dist_to_finish,
+        env.bearing_to_finish,
+        env.velocity.x,
+        env.velocity.y,
+        env.previous_output.acc,
+        env.previous_output.turning_speed,
+    ];
+    for f in fields.iter() {
+        bytes.extend_from_slice(&f.to_le_bytes());
+    }
+    bytes.extend_from_slice(&(env.previous_output.gear as i32).to_le_bytes());
+    bytes.extend_from_slice(&env.time_remaining.as_secs_f32().to_le_bytes());
+    bytes.extend_from_slice(&(env.dist_to_wall.len() as u32).to_le_bytes());
+    for d in env.dist_to_wall.iter() {
+        bytes.extend_from_slice(&d.to_le_bytes());
+    }
+
+    bytes
+}
@@ -3,16 +3,24 @@
 use std::cmp::Ordering;
 use std::collections::BTreeSet;
 
-use crate::sim::Score;
+use crate::code::Source;
+use crate::sim::{CarSetup, Score};
 
 pub struct Leaderboard {
     rankings: BTreeSet<RankedSource>,
+    // The number of entries that have ever been added, used to order entries that would otherwise
+    // tie
+    num_added: u64,
 }
 
 struct RankedSource {
     username: String,
     score: Score,
-    source: String,
+    setup: CarSetup,
+    source: Source,
+    // How many entries were added before this one. No two entries share this, so none of them are
+    // ever equal, and the set never drops an entry for tying with another one
+    order_added: u64,
 }
 
 // The entry corresponding to a single run in the leaderboard. This is essentially just what we're
@@ -21,6 +29,7 @@ struct RankedSource {
 pub struct LeaderboardEntry {
     username: String,
     score: Score,
+    setup: CarSetup,
 }
 
 impl PartialEq for RankedSource {
@@ -37,11 +46,14 @@ impl PartialOrd for RankedSource {
     }
 }
 
+// Better entries are greater, so that the best come last. Equal scores are won by the smaller
+// submission, and after that by whichever was submitted first
 impl Ord for RankedSource {
     fn cmp(&self, other: &Self) -> Ordering {
         self.score
             .cmp(&other.score)
-            .then(self.source.len().cmp(&other.source.len()))
+            .then(other.source.size().cmp(&self.source.size()))
+            .then(other.order_added.cmp(&self.order_added))
     }
 }
 
@@ -49,15 +61,28 @@ impl Leaderboard {
     pub fn new() -> Self {
         Leaderboard {
             rankings: BTreeSet::new(),
+            num_added: 0,
         }
     }
 
-    pub fn add(&mut self, username: String, code: String, score: Score) {
+    pub fn add(&mut self, username: String, code: Source, score: Score, setup: CarSetup) {
         self.rankings.insert(RankedSource {
             username,
             score,
+            setup,
             source: code,
+            order_added: self.num_added,
         });
+        self.num_added += 1;
+    }
+
+    // Returns the source and score of the best entry for the user, if they have one
+    pub fn best_of(&self, username: &str) -> Option<(&Source, Score)> {
+        self.rankings
+            .iter()
+            .rev()
+            .find(|e| e.username == username)
+            .map(|e| (&e.source, e.score))
     }
 
     // Produces an iterator over the top `n` entries in the leaderboard
@@ -69,6 +94,7 @@ impl Leaderboard {
             .map(|e| LeaderboardEntry {
                 username: e.username.clone(),
                 score: e.score.clone(),
+                setup: e.setup,
             })
     }
 }
@@ -3,27 +3,30 @@
 //! This contains the functionality for:
 //! * Receiving & executing user scripts;
 //! * Simulating the car's run around a racetrack;
-//! * Sending back the full race & time; and
-//! * Displaying the leaderboard upon request
+//! * Sending back the full race & time;
+//! * Displaying the leaderboard upon request; and
+//! * Verifying entries in the leaderboard by re-running them
 
 #![feature(decl_macro)]
 
 use lazy_static::lazy_static;
 use rocket::response::status::BadRequest;
-use rocket::{get, post, routes};
+use rocket::{get, post, routes, Data};
 use rocket_contrib::json::Json;
+use std::io::Read;
 use std::sync::Mutex;
+use std::time::Duration;
 
 mod code;
 mod leaderboard;
 mod sim;
 
-use code::Code;
+use code::{Code, ExecLimits, Source};
 use leaderboard::{Leaderboard, LeaderboardEntry};
-use sim::{Racetrack, Simulation, SimulationHistory, SimulationData};
+use sim::{verify_replay, Racetrack, Simulation, SimulationHistory, SimulationData};
 
 //For exResults
-use sim::{Car, Point, Score};
+use sim::{Angle, Car, Outcome, Point, Score, Surface};
 
 lazy_static! {
     static ref LEADERBOARD: Mutex<Leaderboard> = Mutex::new(Leaderboard::new());
@@ -33,24 +36,70 @@ lazy_static! {
 
 type RequestResult<T> = Result<Json<T>, BadRequest<String>>;
 
+// The limits on how long user code can run for during a race
+const EXEC_LIMITS: ExecLimits = ExecLimits {
+    steps_per_call: 1_000_000,
+    time_per_call: Duration::from_millis(50),
+    time_per_race: Duration::from_secs(10),
+    memory_cells: 10_000,
+    // Every race is added to the leaderboard, so every race has to be verifiable
+    deterministic: true,
+};
+
+// The largest WebAssembly module that we'll accept, in bytes
+const MAX_WASM_SIZE: u64 = 4 * 1024 * 1024;
+
 #[post("/run/<username>", data = "<source_code>")]
 fn exec_user_code(
     username: String,
     source_code: String,
 ) -> RequestResult<SimulationData> {
-    let code = Code::from_str(&source_code).map_err(|e| BadRequest(Some(e)))?;
+    run_submission(username, Source::Text(source_code))
+}
+
+#[post("/run-wasm/<username>", data = "<module>")]
+fn exec_user_wasm(username: String, module: Data) -> RequestResult<SimulationData> {
+    let mut bytes = Vec::new();
+    module
+        .open()
+        .take(MAX_WASM_SIZE + 1)
+        .read_to_end(&mut bytes)
+        .map_err(|e| BadRequest(Some(format!("failed to read module: {}", e))))?;
+
+    if bytes.len() as u64 > MAX_WASM_SIZE {
+        return Err(BadRequest(Some(format!(
+            "module is too large; the limit is {} bytes",
+            MAX_WASM_SIZE
+        ))));
+    }
+
+    run_submission(username, Source::Wasm(bytes))
+}
+
+// Runs any kind of submission around the racetrack, adding the result to the leaderboard
+fn run_submission(username: String, source: Source) -> RequestResult<SimulationData> {
+    let code = Code::from_source(&source).map_err(|e| BadRequest(Some(e.to_string())))?;
+    let setup = *code.setup();
 
-    let (score, history) = (Simulation::new(code, &RACETRACK))
+    // Code that fails part of the way through the race still gets a score, so it isn't a bad
+    // request - unless it ran out of time, which doesn't count for anything
+    let (score, history, error) = Simulation::new(code, &RACETRACK, EXEC_LIMITS)
+        .map_err(|e| BadRequest(Some(e)))?
         .simulate()
-        .map_err(|e| BadRequest(Some(e)))?;
+        .map_err(|e| BadRequest(Some(format!("race abandoned without a score: {}", e))))?;
 
     // Add the result of the simulation to the leaderboard
     LEADERBOARD
         .lock()
         .expect("leaderboard mutex already poisoned!")
-        .add(username, source_code, score);
-
-    Ok(Json(SimulationData { history: history, score: score }))
+        .add(username, source, score, setup);
+
+    Ok(Json(SimulationData {
+        history: history,
+        score: score,
+        setup: setup,
+        error: error.map(|e| e.to_string()),
+    }))
 }
 
 #[get("/leaderboard/<n>")]
@@ -62,47 +111,74 @@ fn get_leaderboard(n: usize) -> RequestResult<Vec<LeaderboardEntry>> {
     Ok(Json(entries))
 }
 
+// Re-runs the user's best entry in the leaderboard, to check that it really gets the score it's
+// listed with
+#[get("/verify/<username>")]
+fn verify_entry(username: String) -> RequestResult<Score> {
+    let lb_guard = LEADERBOARD.lock().unwrap();
+    let (source, score) = lb_guard
+        .best_of(&username)
+        .map(|(source, score)| (source.clone(), score))
+        .ok_or_else(|| BadRequest(Some(format!("no entries for '{}'", username))))?;
+    // Re-running the race can take a while, so we shouldn't hold onto the leaderboard meanwhile
+    drop(lb_guard);
+
+    verify_replay(&source, &RACETRACK, EXEC_LIMITS, &score).map_err(|e| BadRequest(Some(e)))?;
+    Ok(Json(score))
+}
+
 fn main() {
     lazy_static::initialize(&RACETRACK);
     lazy_static::initialize(&LEADERBOARD);
     ex_result();
 
     rocket::ignite()
-        .mount("/", routes![exec_user_code, get_leaderboard])
+        .mount("/", routes![exec_user_code, exec_user_wasm, get_leaderboard, verify_entry])
         .launch();
 }
 
 fn ex_result() {
     let s = Score {
-        successful: true,
+        outcome: Outcome::Finished,
         time: 129,
+        impact: None,
     };
 
     let base_car = Car {
         pos: Point { x: 1.0, y: 1.0 },
-        angle: 0.0,
+        angle: Angle::from_degrees(0.0),
         speed: 0.0,
+        slip: Angle::default(),
+        gear: 1,
+        rpm: 1000.0,
+        surface: Surface::Tarmac,
         max_speed: 1.0,
-        max_turn: 1.0,
+        engine_power: 1.0,
+        brake_power: 1.0,
+        max_turn: Angle::from_degrees(1.0),
     };
 
     let h = SimulationHistory {
         history: vec![
-            base_car,
+            base_car.into(),
             Car {
                 pos: Point { x: 1.5, y: 1.5 },
-                angle: 45.0,
+                angle: Angle::from_degrees(45.0),
                 speed: 3.0,
                 ..base_car
-            },
+            }
+            .into(),
             Car {
                 pos: Point { x: 3.5, y: 3.5 },
-                angle: 90.0,
+                angle: Angle::from_degrees(90.0),
                 speed: 12.0,
                 ..base_car
-            },
+            }
+            .into(),
         ],
         tps: 100,
+        splits: Vec::new(),
+        effects: Vec::new(),
     };
 
     let a = Json((h, s));
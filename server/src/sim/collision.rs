@@ -0,0 +1,155 @@
+//! Continuous collision detection between the car and the walls of a [`Racetrack`]
+//!
+//! Within a single physics step, the car first turns to its new angle on the spot, and then moves in
+//! a straight line to its new position. We check both of those parts exactly: the turned car is
+//! checked for overlap with the walls, and then the whole rectangle of the car is swept along its
+//! path. Sweeping the rectangle means checking each corner's path against the walls, and each
+//! wall's endpoints against the car's edges - so neither a thin wall poking into the side of the
+//! car nor a corner clipping the end of a wall can be missed.
+
+use serde::Serialize;
+
+use super::raycast::intersect_segment;
+use super::{Car, Footprint, GridTile, Point, Racetrack};
+
+/// The point at which the car hit a wall
+#[derive(Copy, Clone, Debug, Serialize)]
+pub struct Impact {
+    /// How far through the step the impact happened, from 0 (the start) to 1 (the end). Once it's
+    /// part of a [`Score`](super::Score), this is how far through the whole tick instead
+    pub fraction: f32,
+    /// The point on the wall that the car first touched
+    pub point: Point,
+}
+
+/// Checks whether the car hits a wall while moving from `from` to `to` over a single physics step,
+/// returning the first impact if it does
+///
+/// A car that starts the step off the track entirely counts as hitting a wall immediately.
+pub fn sweep(track: &Racetrack, from: &Car, to: &Car) -> Option<Impact> {
+    if !on_track(track, from.pos) {
+        return Some(Impact {
+            fraction: 0.0,
+            point: from.pos,
+        });
+    }
+
+    let turned = Footprint {
+        centre: from.pos,
+        ..to.footprint()
+    };
+    let (corners, edges) = (turned.corners(), turned.edges());
+    let movement = to.pos - from.pos;
+    let walls = walls_near(track, &corners, movement);
+
+    // First, turning on the spot. If that hits anything, the impact is right at the start of the
+    // step
+    for &(p1, p2) in walls.iter() {
+        if let Some(point) = overlap(&turned, p1, p2) {
+            return Some(Impact {
+                fraction: 0.0,
+                point,
+            });
+        }
+    }
+
+    // Then moving in a straight line. We're looking for the earliest of: any corner of the car
+    // running into a wall, or any wall's endpoint running into an edge of the car (which, relative
+    // to the car, moves in the opposite direction)
+    let mut first: Option<Impact> = None;
+    let mut consider = |fraction: f32, point: Point| {
+        if fraction <= 1.0 && !matches!(first, Some(f) if f.fraction <= fraction) {
+            first = Some(Impact { fraction, point });
+        }
+    };
+
+    for &(p1, p2) in walls.iter() {
+        for &c in corners.iter() {
+            if let Some(t) = intersect_segment(c, movement, p1, p2) {
+                consider(t, c + movement * t);
+            }
+        }
+
+        for &(a, b) in edges.iter() {
+            for &p in [p1, p2].iter() {
+                if let Some(t) = intersect_segment(p, movement * -1.0, a, b) {
+                    consider(t, p);
+                }
+            }
+        }
+    }
+
+    first
+}
+
+// Returns whether the point is in a tile that's at least partly on the track
+fn on_track(track: &Racetrack, p: Point) -> bool {
+    let (x, y) = (p.x / track.tile_size, p.y / track.tile_size);
+    if x < 0.0 || y < 0.0 {
+        return false;
+    }
+
+    match track
+        .grid
+        .get(y as usize)
+        .and_then(|row| row.get(x as usize))
+    {
+        Some(GridTile::Outside) | None => false,
+        Some(_) => true,
+    }
+}
+
+// Collects the border segments in every tile that the car could touch while moving by `movement`
+// from the position given by `corners`
+fn walls_near(track: &Racetrack, corners: &[Point; 4], movement: Point) -> Vec<(Point, Point)> {
+    let (mut min, mut max) = (corners[0], corners[0]);
+    for &c in corners.iter() {
+        for &p in [c, c + movement].iter() {
+            min = Point {
+                x: min.x.min(p.x),
+                y: min.y.min(p.y),
+            };
+            max = Point {
+                x: max.x.max(p.x),
+                y: max.y.max(p.y),
+            };
+        }
+    }
+
+    // Tile indexes are clamped to the grid. Anything outside of it is beyond the outermost border
+    // segments anyway
+    let index =
+        |v: f32, len: usize| (v / track.tile_size).floor().max(0.0).min(len as f32) as usize;
+    let (x_min, x_max) = (index(min.x, track.width), index(max.x, track.width));
+    let (y_min, y_max) = (index(min.y, track.height), index(max.y, track.height));
+
+    let mut walls = Vec::new();
+    for row in track.grid.iter().take(y_max + 1).skip(y_min) {
+        for tile in row.iter().take(x_max + 1).skip(x_min) {
+            if let GridTile::Border { borders, .. } = tile {
+                walls.extend(borders.iter().copied());
+            }
+        }
+    }
+
+    walls
+}
+
+// Returns a point where the wall from `p1` to `p2` overlaps the car, if it does at all
+fn overlap(car: &Footprint, p1: Point, p2: Point) -> Option<Point> {
+    for &(a, b) in car.edges().iter() {
+        if let Some(t) = intersect_segment(a, b - a, p1, p2) {
+            if t <= 1.0 {
+                return Some(a + (b - a) * t);
+            }
+        }
+    }
+
+    // If none of the car's edges cross the wall, the only way that they can overlap is if the
+    // wall is entirely inside the car
+    if car.contains(p1) {
+        Some(p1)
+    } else {
+        None
+    }
+}
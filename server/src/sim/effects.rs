@@ -0,0 +1,45 @@
+//! Tiles that do something to the car when it drives over them
+//!
+//! These go on top of the track's [surfaces](super::Surface): a boost pad or an oil slick is still
+//! tarmac underneath. An effect applies as soon as any part of the car's footprint overlaps its
+//! tile, and every time one fires it's recorded in the replay.
+
+use super::{Angle, Car, Point};
+use serde::Serialize;
+
+/// The speed that a boost pad adds to the car, in units per tick
+pub const BOOST_SPEED: f32 = 3.0;
+/// The number of ticks that the car keeps sliding around for after running over an oil slick
+pub const OIL_TICKS: u32 = 20;
+/// The most that an oil slick can turn the car by in a single tick, in degrees. The heading
+/// wanders randomly by up to this much every tick while the oil lasts
+pub const OIL_WOBBLE: f32 = 15.0;
+
+/// Something that happens to the car when it drives onto a tile
+#[derive(Copy, Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "effect", rename_all = "snake_case")]
+pub enum Effect {
+    /// Pushes the car in the given direction, adding [`BOOST_SPEED`] to its velocity
+    Boost { direction: Angle },
+    /// Sends the car's heading wandering randomly for [`OIL_TICKS`] ticks
+    OilSlick,
+    /// Acts as a wall for any car that drives onto it going against the given direction. Cars
+    /// going the right way (or across it) aren't affected
+    OneWayGate { direction: Angle },
+}
+
+/// Adds the boost from a boost pad facing in `direction` to the car's velocity
+///
+/// The car keeps facing the same way, so unless the pad points the way it's already going, this
+/// sets it sliding.
+pub fn boost(car: &mut Car, direction: Angle) {
+    let velocity =
+        Point::new_polar(car.speed, car.direction()) + Point::new_polar(BOOST_SPEED, direction);
+    car.speed = velocity.length();
+    car.slip = (velocity.angle() - car.angle).normalised();
+}
+
+/// Returns whether a one-way gate facing in `direction` blocks the car
+pub fn blocks(car: &Car, direction: Angle) -> bool {
+    car.speed > 0.0 && (car.direction() - direction).cos() < 0.0
+}
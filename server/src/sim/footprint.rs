@@ -0,0 +1,168 @@
+//! The footprint of the car: the rectangle that it covers on the track
+//!
+//! Everything that cares about the shape of the car - collision detection, crossing the finish
+//! line and drawing replays - goes through [`Footprint`], so that they all agree on exactly where
+//! the car is.
+
+use super::{Angle, Point};
+
+/// A rectangle that can be rotated to any angle, given by its centre and heading
+#[derive(Copy, Clone, Debug)]
+pub struct Footprint {
+    /// The centre of the rectangle
+    pub centre: Point,
+    /// The direction that the front of the rectangle points in
+    pub heading: Angle,
+    /// The length of the rectangle, from back to front
+    pub length: f32,
+    /// The width of the rectangle, from side to side
+    pub width: f32,
+}
+
+impl Footprint {
+    /// Returns the corners of the rectangle, going clockwise from the front left
+    pub fn corners(&self) -> [Point; 4] {
+        let forward = Point::new_polar(self.length / 2.0, self.heading);
+        let left = Point::new_polar(self.width / 2.0, self.heading + Angle::from_degrees(90.0));
+
+        [
+            self.centre + forward + left,
+            self.centre + forward - left,
+            self.centre - forward - left,
+            self.centre - forward + left,
+        ]
+    }
+
+    /// Returns the edges of the rectangle, each going from one corner to the next in the same order
+    /// as [`corners`](Self::corners) - so the first edge is the front
+    pub fn edges(&self) -> [(Point, Point); 4] {
+        let c = self.corners();
+        [(c[0], c[1]), (c[1], c[2]), (c[2], c[3]), (c[3], c[0])]
+    }
+
+    /// Returns whether the rectangle overlaps the axis-aligned box from `min` to `max`. Just
+    /// touching the box counts as overlapping it
+    pub fn overlaps_box(&self, min: Point, max: Point) -> bool {
+        // The two shapes overlap unless there's a line between them. For two rectangles, that
+        // line can always be taken parallel to one of their edges
+        let c = self.corners();
+        if c.iter().all(|p| p.x < min.x)
+            || c.iter().all(|p| p.x > max.x)
+            || c.iter().all(|p| p.y < min.y)
+            || c.iter().all(|p| p.y > max.y)
+        {
+            return false;
+        }
+
+        // The corners go clockwise, so the inside of the rectangle is to the right of each edge
+        let box_corners = [
+            min,
+            Point { x: max.x, y: min.y },
+            max,
+            Point { x: min.x, y: max.y },
+        ];
+        !self
+            .edges()
+            .iter()
+            .any(|&(a, b)| box_corners.iter().all(|&p| (b - a).cross(p - a) > 0.0))
+    }
+
+    /// Returns whether the point is inside the rectangle (or on its edge)
+    pub fn contains(&self, p: Point) -> bool {
+        let sides: Vec<f32> = self
+            .edges()
+            .iter()
+            .map(|&(a, b)| (b - a).cross(p - a))
+            .collect();
+        sides.iter().all(|s| *s >= 0.0) || sides.iter().all(|s| *s <= 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn footprint(x: f32, y: f32, degrees: f32, length: f32, width: f32) -> Footprint {
+        Footprint {
+            centre: Point { x, y },
+            heading: Angle::from_degrees(degrees),
+            length,
+            width,
+        }
+    }
+
+    fn assert_corners(footprint: &Footprint, expected: [(f32, f32); 4]) {
+        for (corner, &(x, y)) in footprint.corners().iter().zip(expected.iter()) {
+            assert!(
+                (corner.x - x).abs() < 1e-5 && (corner.y - y).abs() < 1e-5,
+                "expected corners {:?}, got {:?}",
+                expected,
+                footprint.corners()
+            );
+        }
+    }
+
+    #[test]
+    fn corners_facing_right() {
+        let f = footprint(1.0, 2.0, 0.0, 2.0, 1.0);
+        assert_corners(&f, [(2.0, 2.5), (2.0, 1.5), (0.0, 1.5), (0.0, 2.5)]);
+    }
+
+    #[test]
+    fn corners_facing_up() {
+        let f = footprint(1.0, 2.0, 90.0, 2.0, 1.0);
+        assert_corners(&f, [(0.5, 3.0), (1.5, 3.0), (1.5, 1.0), (0.5, 1.0)]);
+    }
+
+    #[test]
+    fn corners_at_45_degrees() {
+        // Half of the length is (1, 1) and half of the width is (-0.5, 0.5)
+        let f = footprint(0.0, 0.0, 45.0, 8f32.sqrt(), 2f32.sqrt());
+        assert_corners(&f, [(0.5, 1.5), (1.5, 0.5), (-0.5, -1.5), (-1.5, -0.5)]);
+    }
+
+    #[test]
+    fn edges_go_around_the_corners() {
+        for &degrees in [0.0, 90.0, 45.0].iter() {
+            let f = footprint(1.0, 2.0, degrees, 2.0, 1.0);
+            let corners = f.corners();
+            let edges = f.edges();
+            let xy = |p: Point| (p.x, p.y);
+            for i in 0..4 {
+                assert_eq!(xy(edges[i].0), xy(corners[i]));
+                assert_eq!(xy(edges[i].1), xy(corners[(i + 1) % 4]));
+            }
+        }
+
+        // The first edge is the front of the car
+        let (a, b) = footprint(1.0, 2.0, 0.0, 2.0, 1.0).edges()[0];
+        assert_eq!((a.x, b.x), (2.0, 2.0));
+    }
+
+    #[test]
+    fn contains() {
+        let p = |x, y| Point { x, y };
+
+        let right = footprint(1.0, 2.0, 0.0, 2.0, 1.0);
+        assert!(right.contains(p(1.0, 2.0)));
+        assert!(right.contains(p(1.9, 2.4)));
+        assert!(right.contains(p(2.0, 2.0)));
+        assert!(!right.contains(p(2.1, 2.0)));
+        assert!(!right.contains(p(1.0, 2.6)));
+
+        let up = footprint(1.0, 2.0, 90.0, 2.0, 1.0);
+        assert!(up.contains(p(1.0, 2.9)));
+        assert!(up.contains(p(0.6, 1.1)));
+        assert!(!up.contains(p(1.6, 2.0)));
+        assert!(!up.contains(p(1.0, 3.1)));
+
+        let diagonal = footprint(0.0, 0.0, 45.0, 8f32.sqrt(), 2f32.sqrt());
+        assert!(diagonal.contains(p(0.0, 0.0)));
+        assert!(diagonal.contains(p(0.9, 0.9)));
+        assert!(diagonal.contains(p(1.0, 0.5)));
+        assert!(!diagonal.contains(p(1.2, 1.2)));
+        // Inside the box around the rectangle, but not the rectangle itself
+        assert!(!diagonal.contains(p(-1.0, 1.0)));
+        assert!(!diagonal.contains(p(1.4, 1.4)));
+    }
+}
@@ -1,307 +1,696 @@
-use crate::code::{Code, ExecEnvironment};
+use crate::code::{Budget, Code, CodeError, ExecEnvironment, ExecLimits, Memory, Output, Source};
 use serde::Serialize;
+use std::cmp::Ordering;
 
+mod collision;
+mod effects;
+mod footprint;
 mod point;
 mod racetrack;
-
-pub use point::Point;
-pub use racetrack::{Car, GridTile, Racetrack, CAR_LENGTH, CAR_WIDTH};
+mod raycast;
+mod sensors;
+mod setup;
+mod smoothing;
+pub mod strict;
+mod surface;
+mod track_header;
+
+pub use collision::Impact;
+pub use effects::Effect;
+pub use footprint::Footprint;
+pub use point::{Angle, Point};
+pub use racetrack::{Car, GridTile, Racetrack, CAR_LENGTH, CAR_WIDTH, NUM_GEARS};
+pub use sensors::{SensorRig, MAX_RAYS};
+pub use setup::{CarSetup, SetupLimits, SETUP_POINTS_CAP};
+pub use surface::Surface;
+use sensors::Noise;
 
 // A tick is the unit on which thte simulation will update the world
 const TICKS_PER_SECOND: i32 = 100;
 // The number of ticks until the users code will be asked what it wants to do next
 const TICKS_PER_UPDATE: i32 = 10;
-// The number of checks/ unit dist along a line that the car travels to make sure it never goes out of bounds
-const NUMBER_CHECKS_PER_UNIT_DIST: f32 = 10.0;
-// The maximum error acceptable when giving the distance to the wall to the User
-const ACCURACY_OF_DIST_TO_WALL :f32 = 0.001;
-// The number of angles to check the distance to the wall at
-// MUST - devide 360 in the ring on integers
-const NUMBER_ANGLES_TO_CHECK :usize = 60;
+// The number of physics steps in each tick. More steps make the physics more accurate (especially
+// at high speeds) without changing how often the user's code is asked for directions, or what the
+// client is sent - which is still one `Car` per tick
+const SUBSTEPS_PER_TICK: u32 = 4;
+// The length of a single physics step, as a fraction of a tick. All of the car's rates are given
+// per tick, so they're scaled by this for each step
+const STEP: f32 = 1.0 / SUBSTEPS_PER_TICK as f32;
 
 // Almost all the computation will be done in the Simulation Object
 
 pub struct Simulation {
     code: Code,
+    limits: ExecLimits,
     track: &'static Racetrack,
     car: Car,
-    // For i circuits to have to be done laps = 4 * i (as car has 4 corners)
+    // The number of laps left to finish, including the one in progress
     laps: i32,
+    // The index of the next checkpoint that the car has to cross. Once it's crossed all of them,
+    // this is `track.checkpoints.len()`, and crossing the finish line completes the lap
+    next_checkpoint: usize,
+    // The number of times the car has to cross the finish line going forwards before it counts:
+    // once for each time it's crossed back over the line going the wrong way, plus once at the
+    // start if it starts behind the line
+    behind_finish: i32,
+    // The effects of the tiles that the car was touching after the last physics step, so that each
+    // one only fires when the car first runs onto it
+    touching: Vec<Effect>,
+    // The number of physics steps left before the car gets clear of an oil slick
+    oiled: u32,
+    // The source of noise for the sensor rig, and of randomness for oil slicks
+    noise: Noise,
+}
+
+/// How a race ended
+#[derive(Copy, Clone, Serialize, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The car crossed the finish line
+    Finished,
+    /// The car was still going when the track's tick limit ran out
+    TimedOut,
+    /// The car hit a wall
+    Crashed,
+    /// The user's code failed part of the way through the race
+    CodeError,
+}
+
+impl Outcome {
+    // How good the outcome is, from worst to best, for comparing scores
+    fn rank(self) -> u8 {
+        match self {
+            Outcome::CodeError => 0,
+            Outcome::Crashed => 1,
+            Outcome::TimedOut => 2,
+            Outcome::Finished => 3,
+        }
+    }
 }
 
 //TODO - Made field public for score + sim hist pub for ex result
 // TODO - added debug for ex result
-#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Debug)]
+#[derive(Copy, Clone, Serialize, Debug)]
 pub struct Score {
-    pub successful: bool,
+    pub outcome: Outcome,
     pub time: i32, // In terms of ticks
+    // Where the car crashed, if it did. This isn't taken into account when comparing scores
+    pub impact: Option<Impact>,
+}
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Better scores are greater. A better outcome always wins; between two finishes the faster one
+/// wins, and between two races that didn't finish, the one that lasted longer wins
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let time = match self.outcome {
+            Outcome::Finished => other.time.cmp(&self.time),
+            _ => self.time.cmp(&other.time),
+        };
+
+        self.outcome.rank().cmp(&other.outcome.rank()).then(time)
+    }
+}
+
+impl Score {
+    /// Returns whether the two scores are exactly the same, down to the bits of where the car
+    /// crashed. Unlike `==`, which only compares how good the scores are
+    pub fn is_identical(&self, other: &Score) -> bool {
+        let impact_bits = |s: &Score| {
+            s.impact
+                .map(|i| (i.fraction.to_bits(), i.point.x.to_bits(), i.point.y.to_bits()))
+        };
+        self == other && impact_bits(self) == impact_bits(other)
+    }
 }
 
 // TODO - added debug for ex result
 #[derive(Serialize, Debug)]
 pub struct SimulationHistory {
-    pub history: Vec<Car>,
+    pub history: Vec<Frame>,
     pub tps: i32, // Ticks per second used for this simulation
+    pub splits: Vec<Split>, // Every checkpoint and finish line crossing, in order
+    pub effects: Vec<EffectEvent>, // Every time that a tile's effect fired, in order
 }
 
+/// The time at which one of the track's [effects](Effect) fired
 #[derive(Serialize, Debug)]
-pub struct SimulationData {
-    pub history : SimulationHistory,
-    pub score : Score,
+pub struct EffectEvent {
+    pub time: i32, // In terms of ticks
+    #[serde(flatten)]
+    pub effect: Effect,
 }
 
-impl Simulation {
-    fn make_environment(&self) -> ExecEnvironment {
-        let go_dist = | start: Point, dist: f32, angle :f32|{
-            let traveled = Point {
-                x: angle.cos() * dist,
-                y: angle.sin() * dist,
-            };
-            start + traveled
-        };
-
-        let f = | angle :f32 | {
-            let mut dist_traveled = 0.0;
-            let mut precision = 1.0;
-            let mut start = self.car.pos;
-            let mut end = go_dist(start, precision, angle);
-
-            while precision > ACCURACY_OF_DIST_TO_WALL{
-                let hit = self.hit_wall(start, end);
-                if hit {
-                    dist_traveled += precision;
-                    precision *= 2.0;
-                    start = end;
-                } else {
-                    precision /= 2.0;
-                }
-                end = go_dist(start, precision, angle);
+/// The time at which the car crossed one of the track's checkpoints, or the finish line
+#[derive(Serialize, Debug)]
+pub struct Split {
+    /// The lap that the car was on, starting from 1
+    pub lap: i32,
+    /// The number of the checkpoint, starting from 1. `None` is the finish line, which ends the lap
+    pub checkpoint: Option<usize>,
+    pub time: i32, // In terms of ticks
+}
 
-            }
+// The ways that the car can cross a line
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum Crossing {
+    Forwards,
+    Backwards,
+}
 
-            dist_traveled
-        };
-        let mut dists = Vec::with_capacity(NUMBER_ANGLES_TO_CHECK);
-        let angle_delta = 360/NUMBER_ANGLES_TO_CHECK;
+// Which way the path from `start` to `end` crosses the line from `line.0` to `line.1`, if it does.
+// Crossing towards the side of the line that `forwards` points to is forwards. Only touching the
+// line, without reaching the other side, doesn't count
+fn crossing(line: (Point, Point), forwards: Point, start: Point, end: Point) -> Option<Crossing> {
+    let (p1, p2) = line;
+    let along = p2 - p1;
+    let (from, to) = (forwards.dot(start - p1), forwards.dot(end - p1));
+
+    let direction = if from < 0.0 && to >= 0.0 {
+        Crossing::Forwards
+    } else if from >= 0.0 && to < 0.0 {
+        Crossing::Backwards
+    } else {
+        return None;
+    };
+
+    // The sides have different signs, so `from - to` can't be zero. `s` is how far along the line
+    // the path crosses it, from 0 at `p1` to 1 at `p2`
+    let p = start + (end - start) * (from / (from - to)) - p1;
+    let s = p.dot(along) / along.dot(along);
+    if (0.0..=1.0).contains(&s) {
+        Some(direction)
+    } else {
+        None
+    }
+}
 
-        for i in 0..NUMBER_ANGLES_TO_CHECK {
-            dists.push(f((i*angle_delta) as f32))
-        }
+// The middle of the front of the car, which is where progress around the track is measured
+fn front(car: Car) -> Point {
+    let corners = car.footprint().corners();
+    (corners[0] + corners[1]) / 2.0
+}
 
+/// The state of the car at a single tick of a replay
+///
+/// Along with the car itself, this gives the corners of its [`Footprint`], so that replays can draw
+/// the car exactly where the simulation had it.
+#[derive(Serialize, Debug)]
+pub struct Frame {
+    #[serde(flatten)]
+    pub car: Car,
+    pub corners: [Point; 4],
+}
 
-        ExecEnvironment{
-            car_currently : self.car,
-            dist_to_wall :  dists,
+impl From<Car> for Frame {
+    fn from(car: Car) -> Self {
+        Frame {
+            car,
+            corners: car.footprint().corners(),
         }
     }
+}
+
+#[derive(Serialize, Debug)]
+pub struct SimulationData {
+    pub history : SimulationHistory,
+    pub score : Score,
+    pub setup : CarSetup, // The car setup that the code chose
+    pub error : Option<String>, // Why the code failed, if the outcome was a code error
+}
+
+impl Simulation {
+    fn make_environment(&mut self, ticks: i32, budget: &Budget, previous: Output) -> ExecEnvironment {
+        let rig = self.code.sensors();
+        let (track, car, noise) = (self.track, self.car, &mut self.noise);
+        let dists = rig.angles.iter()
+            .map(|a| {
+                let d = raycast::distance_to_wall(track, car.pos, car.angle + *a, rig.range);
+                noise.apply(rig, d)
+            })
+            .collect();
 
-    // Checks the car goes over the finishline the correct number of times to finish the game
-    fn passed_finish_line(&mut self, start: Point, end: Point) -> bool {
         let (p1, p2) = self.track.finish_line;
+        let to_finish = (p1 + p2) / 2.0 - self.car.pos;
+        let bearing = (to_finish.angle() - self.car.angle).normalised();
 
-        let intersection = Simulation::intersection_of_2_lines(start, end, p1, p2);
-
-        //TODO : If p2.x = p1.x then it breaks FIX
-        let ycheck = p1.y + (p2.y - p1.y) * (p1.x - start.x)/ (p2.x - p1.x);
-
-        //Tells you if coming from the correct direction
-        // TODO : may break if doesn't start at 180 angle
-        let correct_direction = ycheck <= start.y;
-
-        match intersection {
-            None => false,
-            Some(p) => {
-                if Simulation::between_2_points(p1,p2, p) && Simulation::between_2_points(start, end, p){
-                    if correct_direction && (self.laps == 0){
-                        true
-                    } else if correct_direction {
-                        self.laps -= 1;
-                        false
-                    } else {
-                        self.laps += 1;
-                        false
-                    }
-                } else {
-                    false
-                }
+        ExecEnvironment{
+            car_currently : self.car,
+            dist_to_wall :  dists,
+            tick: ticks,
+            laps_remaining: self.laps,
+            dist_to_finish: to_finish.length(),
+            bearing_to_finish: bearing.radians(),
+            velocity: Point::new_polar(self.car.speed, self.car.direction()),
+            previous_output: previous,
+            // The time remaining depends on how fast the server is, so it has to be hidden if the
+            // race needs to be reproducible
+            time_remaining: if budget.deterministic() {
+                self.limits.time_per_race
+            } else {
+                budget.remaining()
             },
         }
     }
 
-    fn between_2_points(p1:Point, p2: Point, point_2_compare: Point) -> bool {
-        let xbetween = p1.x >= point_2_compare.x && p2.x <= point_2_compare.x
-                    || p1.x <= point_2_compare.x && p2.x >= point_2_compare.x;
+    // Keeps track of the car's progress around the track as its front moves from `start` to `end`,
+    // returning whether it's just finished the race
+    //
+    // A lap only counts if the car crosses every checkpoint in order, and then the finish line
+    // going forwards. Crossing the finish line backwards has to be undone before it counts again,
+    // so wiggling back and forth over it doesn't get the car anywhere.
+    fn track_progress(
+        &mut self,
+        start: Point,
+        end: Point,
+        ticks: i32,
+        splits: &mut Vec<Split>,
+    ) -> bool {
+        let track = self.track;
+        let lap = track.laps - self.laps + 1;
+
+        if let Some(&line) = track.checkpoints.get(self.next_checkpoint) {
+            // Checkpoints can be crossed either way, so it doesn't matter which side is forwards
+            let (p1, p2) = line;
+            let across = Point { x: p2.y - p1.y, y: p1.x - p2.x };
+            if crossing(line, across, start, end).is_some() {
+                self.next_checkpoint += 1;
+                splits.push(Split { lap, checkpoint: Some(self.next_checkpoint), time: ticks });
+            }
+        }
 
-        let ybetween = p1.y >= point_2_compare.y && p2.y <= point_2_compare.y
-                    || p1.y <= point_2_compare.y && p2.y >= point_2_compare.y;
+        match crossing(track.finish_line, track.finish_normal, start, end) {
+            Some(Crossing::Backwards) => self.behind_finish += 1,
+            Some(Crossing::Forwards) if self.behind_finish > 0 => self.behind_finish -= 1,
+            Some(Crossing::Forwards) if self.next_checkpoint == track.checkpoints.len() => {
+                splits.push(Split { lap, checkpoint: None, time: ticks });
+                self.laps -= 1;
+                self.next_checkpoint = 0;
+            }
+            _ => (),
+        }
 
-        xbetween && ybetween
+        self.laps == 0
     }
 
-    fn intersection_of_2_lines(s1: Point, e1: Point, s2:Point, e2:Point) -> Option<Point> {
-        // Line s1 e1 represented a1x + b1y = c1
-        let a1 = s1.y - e1.y;
-        let b1 = s1.x - e1.x;
-        let c1 = a1*s1.x + b1*s1.y;
-
-        // Line s2 e2 represented as a2x + b2y = c2
-        let a2 = s2.y - e2.y;
-        let b2 = s2.x - e2.x;
-        let c2 = a2*s2.x + b2*s2.y;
+    // Positive `acc` is how far the accelerator is down, and negative `acc` is how far the brake
+    // is down. Braking only ever brings the car to a stop; it doesn't reverse
+    fn speed_after_step(&self, acc: f32) -> f32 {
+        let car = self.car;
+        let acc = acc.clamp(-1.0, 1.0);
+
+        // The engine can't drive the car any faster than its surface allows. A car that's already
+        // going faster than that isn't slowed down by it directly; that's up to the resistance
+        let actual_acc = if acc >= 0.0 && car.speed >= car.top_speed() {
+            0.0
+        } else if acc >= 0.0 {
+            acc * car.max_acc()
+        } else {
+            acc * car.max_dec()
+        };
 
-        let det = a1*b2 - a2*b1;
+        let limit = car.top_speed().max(car.speed);
+        (car.speed + (actual_acc - car.resistance()) * STEP).max(0.0).min(limit)
+    }
 
-        if det.abs() <= 0.00000001 {
-            None
+    // A gear of 0 leaves the choice to the automatic gearbox. Anything else has already been
+    // checked to be a valid gear
+    fn gear_after_step(&self, gear: u8) -> u8 {
+        if gear == 0 {
+            self.car.best_gear()
         } else {
-            let x = (b2*c1 - b1*c2)/det;
-            let y = (a1*c2 - a2*c1)/det;
-            Some(Point{x, y})
+            gear
         }
     }
 
-    // TODO - make more intelligent decisions about when the car should die
-    // ASSUMES - that the car is thinner than 1 unit
-    fn hit_wall(&self, start: Point, end: Point) -> bool {
-        let mut point_checking = start;
-        let num_checks : i32 = ((end - start).length() * NUMBER_CHECKS_PER_UNIT_DIST) as i32;
-        let delta = (end - start) / (num_checks as f32);
-
-        for _i in 0..=num_checks {
-            if !self.in_bounds(point_checking) {
-                return true;
-            }
-            point_checking += delta;
-        }
-        false
+    // `turning_speed` is in degrees per tick, but the car can only turn as fast as its current
+    // speed allows
+    fn angle_after_step(&self,turning_speed: f32) -> Angle {
+        let max = self.car.max_yaw_rate() * STEP;
+        let turn = (Angle::from_degrees(turning_speed) * STEP).clamp(-max, max);
+        (self.car.angle + turn).normalised()
     }
 
-    //TODO: Research air resistance
-    fn speed_after_tick(&self, acc: f32) -> f32 {
-        let car = self.car;
-        let actual_acc = acc * car.max_acc();
+    // The tyres pull the direction the car is moving in towards the direction it's facing, but
+    // only as quickly as their grip allows. Whatever's left over is the new slip angle
+    fn slip_after_step(&self, angle: Angle) -> Angle {
+        let direction = self.car.direction();
+        let max = self.car.max_grip_rate() * STEP;
+        let turn = (angle - direction).normalised().clamp(-max, max);
 
-        (car.speed + actual_acc).max(car.max_speed)
+        (direction + turn - angle).normalised()
     }
 
-    //TODO: Don't let them turn at any speed per tick like a god damn owl
-    fn angle_after_tick(&self,turning_speed: f32) -> f32 {
-        self.car.angle + turning_speed
-    }
+    // Applies the effects of the tiles that the car is touching, recording every one that fires.
+    // Driving the wrong way through a one-way gate counts as hitting a wall, which is returned
+    //
+    // Tiles next to each other with the same effect act as a single pad, so that a strip of boost
+    // pads only boosts the car once: effects only fire when the car first runs onto them.
+    fn apply_effects(&mut self, ticks: i32, events: &mut Vec<EffectEvent>) -> Option<Impact> {
+        let mut touching = Vec::new();
+        for effect in self.track.effects_touching(&self.car.footprint()) {
+            if !touching.contains(&effect) {
+                touching.push(effect);
+            }
+        }
 
-    // TODO - Probably should use more advanced line system
-    fn in_bounds(&self, point: Point) -> bool {
-        let square = self.track.grid[point.x as usize][point.y as usize];
-        match square {
-            GridTile::Outside => false,
-            GridTile::Inside { .. } => true,
-            GridTile::Border {
-                border: (p1, p2), ..
-            } => {
-                // Boolean value to indicate if `point` is "underneath" the line from `p1` to `p2`,
-                // where "underneath" is the same direction w.r.t. p1 and p2, regardless of the
-                // frame of reference.
-                //
-                // The boolean value is true if p2.x > p1.x and point.y is below the line from p1
-                // to p2.
-                let is_underneath = |p: Point| {
-                    let amount = (p.x - p1.x) * (p2.y - p1.y) - (p.y - p1.y) * (p2.x - p1.x);
-                    amount < 0.0
-                };
-                let base_is_underneath = is_underneath(point);
+        let mut impact = None;
+        for &effect in touching.iter() {
+            let entered = !self.touching.contains(&effect);
+            let fired = match effect {
+                Effect::Boost { direction } if entered => {
+                    effects::boost(&mut self.car, direction);
+                    true
+                }
+                Effect::OilSlick if entered => {
+                    self.oiled = effects::OIL_TICKS * SUBSTEPS_PER_TICK;
+                    true
+                }
+                Effect::OneWayGate { direction } if effects::blocks(&self.car, direction) => {
+                    let corners = self.car.footprint().corners();
+                    impact = Some(Impact {
+                        fraction: 1.0,
+                        point: (corners[0] + corners[1]) / 2.0,
+                    });
+                    true
+                }
+                _ => false,
+            };
 
-                // Now need to work out which neighbours are on the same side of the square to check to see if they're inside
-                let center = Point {
-                    x: point.x.floor() + 0.5,
-                    y: point.y.floor() + 0.5,
-                };
+            if fired {
+                events.push(EffectEvent { time: ticks, effect });
+            }
+        }
 
-                let up = center.add_y(1.0);
-                let left = center.add_x(-1.0);
-                let down = center.add_y(-1.0);
-                let right = center.add_x(1.0);
+        self.touching = touching;
+        impact
+    }
 
-                let on_inside = [up, left, down, right].iter().cloned().any(|p| {
-                    is_underneath(p) == base_is_underneath && self.track.get_tile(p).is_inside()
-                });
+    // The random amount that an oil slick turns the car by in a single physics step
+    fn oil_wobble(&mut self) -> Angle {
+        let max = Angle::from_degrees(effects::OIL_WOBBLE) * STEP;
+        max * (2.0 * self.noise.uniform() - 1.0)
+    }
 
-                on_inside
-            }
+    // Asks the user's code for its next directions
+    //
+    // If a single decision goes over its limits, that isn't fatal; the car just carries on with
+    // its `previous` directions. Any other error ends the simulation.
+    fn decide(
+        &mut self,
+        ticks: i32,
+        budget: &mut Budget,
+        memory: &mut Memory,
+        previous: Output,
+    ) -> Result<Output, CodeError> {
+        let env = self.make_environment(ticks, budget, previous);
+        match self.code.execute(&env, budget, memory) {
+            Err(CodeError::Timeout(_)) => Ok(previous),
+            res => res,
         }
     }
 
-    // The users affect on the car happen at the start of the tick (before calculating new position)
-    pub fn simulate(mut self) -> Result<(Score, SimulationHistory), String> {
-        // If Car more than 1 unit wide will break wall collision - (as only check at the corners so
-        //  in the situation below the car could drive straight over the x
-        //  +---------+
-        //  |         |   x
-        //  +---------+
-        assert!(CAR_WIDTH < 1.0);
-
+    /// Runs the race to the end, giving the score and replay along with the error that ended it, if
+    /// the user's code failed
+    ///
+    /// The race always ends: the car either finishes, crashes, runs out of ticks or its code fails.
+    /// With [deterministic](ExecLimits::deterministic) limits, code that runs out of wall-clock
+    /// time doesn't get a score at all, since whether that happens depends on the server rather
+    /// than the code. The race is abandoned, giving back the error instead.
+    pub fn simulate(mut self) -> Result<(Score, SimulationHistory, Option<CodeError>), CodeError> {
         let mut hist = SimulationHistory {
             history: Vec::new(),
             tps: TICKS_PER_SECOND,
+            splits: Vec::new(),
+            effects: Vec::new(),
         };
-
         let mut ticks = 0;
-        // TODO: Maybe not all code execution errors should be returned here? - e.g. timeouts
-        // should maybe not set `action`
-        let mut action = self.code.execute(&self.make_environment())?;
+
+        match self.race(&mut hist, &mut ticks) {
+            Ok(score) => Ok((score, hist, None)),
+            Err(e) if self.limits.deterministic && e.is_wall_clock() => Err(e),
+            Err(e) => {
+                // The replay stops at the last tick before the code failed
+                let score = Score {
+                    outcome: Outcome::CodeError,
+                    time: ticks,
+                    impact: None,
+                };
+                Ok((score, hist, Some(e)))
+            }
+        }
+    }
+
+    // The users affect on the car happen at the start of the tick (before calculating new position)
+    //
+    // `ticks` is kept up to date as the race goes on, so that it's still known if the code fails
+    fn race(&mut self, hist: &mut SimulationHistory, ticks: &mut i32) -> Result<Score, CodeError> {
+        let mut budget = Budget::new(self.limits);
+        let mut memory = Memory::new(self.limits.memory_cells);
+        // If the very first decision times out, there's nothing to fall back on, so the car starts
+        // off coasting
+        let mut action = self.decide(*ticks, &mut budget, &mut memory, Output::default())?;
         let mut passed_finish = false;
 
         while !passed_finish {
-            // Here, we additionally check if ticks != 0 because it's the initial value of `action`
-            if ticks % TICKS_PER_UPDATE == 0 && ticks != 0 {
-                action = self.code.execute(&self.make_environment())?;
+            // A car that never reaches the finish (or a wall) would otherwise keep the race going
+            // forever
+            if *ticks >= self.track.tick_limit {
+                return Ok(Score {
+                    outcome: Outcome::TimedOut,
+                    time: *ticks,
+                    impact: None,
+                });
             }
-            ticks += 1;
-
-            let start_pos = self.car.pos_of_corners();
 
-            self.car.speed = self.speed_after_tick(action.acc);
-            self.car.angle = self.angle_after_tick(action.turning_speed);
+            // Here, we additionally check if ticks != 0 because it's the initial value of `action`
+            if *ticks % TICKS_PER_UPDATE == 0 && *ticks != 0 {
+                action = self.decide(*ticks, &mut budget, &mut memory, action)?;
+            }
+            *ticks += 1;
+
+            for step in 0..SUBSTEPS_PER_TICK {
+                let start = self.car;
+
+                self.car.gear = self.gear_after_step(action.gear);
+                self.car.speed = self.speed_after_step(action.acc);
+                self.car.rpm = self.car.rpm_in_gear(self.car.gear);
+                let mut angle = self.angle_after_step(action.turning_speed);
+                if self.oiled > 0 {
+                    angle = (angle + self.oil_wobble()).normalised();
+                    self.oiled -= 1;
+                }
+                self.car.slip = self.slip_after_step(angle);
+                self.car.angle = angle;
 
-            self.car.pos += Point::new_polar(self.car.speed, self.car.angle);
+                self.car.pos += Point::new_polar(self.car.speed * STEP, self.car.direction());
+                self.car.surface = self.track.surface_at(self.car.pos);
 
-            hist.history.push(self.car);
+                let impact = match collision::sweep(self.track, &start, &self.car) {
+                    Some(impact) => Some(impact),
+                    None => self.apply_effects(*ticks, &mut hist.effects),
+                };
 
-            let end_pos = self.car.pos_of_corners();
+                if let Some(impact) = impact {
+                    // The last entry in the history shows the car at the moment it crashed, rather
+                    // than where it would have been
+                    self.car.pos = start.pos + (self.car.pos - start.pos) * impact.fraction;
+                    hist.history.push(self.car.into());
 
-            for (s,f) in start_pos.iter().zip(end_pos.iter()) {
-                if self.hit_wall(*s, *f) {
                     let score = Score {
-                        successful: false,
-                        time: ticks,
+                        outcome: Outcome::Crashed,
+                        time: *ticks,
+                        // The impact is given as a fraction of the whole tick, not just this step
+                        impact: Some(Impact {
+                            fraction: (step as f32 + impact.fraction) * STEP,
+                            ..impact
+                        }),
                     };
 
-                    return Ok((score, hist));
+                    return Ok(score);
                 }
-            }
 
+                passed_finish =
+                    self.track_progress(front(start), front(self.car), *ticks, &mut hist.splits);
 
-            for (s,f) in start_pos.iter().zip(end_pos.iter()) {
-                if self.passed_finish_line(*s, *f) {
-                    passed_finish = true
+                if passed_finish {
+                    break;
                 }
             }
 
+            hist.history.push(self.car.into());
         }
 
-        let score = Score {
-            successful: true,
-            time: ticks,
-        };
-
-        Ok((score, hist))
+        Ok(Score {
+            outcome: Outcome::Finished,
+            time: *ticks,
+            impact: None,
+        })
     }
 
-    pub fn new(code: Code, track: &'static Racetrack) -> Self {
-        Simulation {
+    // Fails if the code's sensor rig or car setup isn't allowed
+    pub fn new(code: Code, track: &'static Racetrack, limits: ExecLimits) -> Result<Self, String> {
+        code.sensors().validate()?;
+        code.setup().validate(&track.setup_limits)?;
+
+        let car = track.initial_car_state.with_setup(code.setup());
+        // The car usually starts just behind the finish line, so the first time it crosses the line
+        // only starts the race
+        let (p1, _) = track.finish_line;
+        let behind_finish = if track.finish_normal.dot(front(car) - p1) < 0.0 { 1 } else { 0 };
+
+        Ok(Simulation {
+            car,
             code,
+            limits,
             track,
-            car: track.initial_car_state,
-            laps: 4*track.laps,
-        }
+            laps: track.laps,
+            next_checkpoint: 0,
+            behind_finish,
+            touching: Vec::new(),
+            oiled: 0,
+            noise: Noise::default(),
+        })
+    }
+}
+
+/// Re-runs a stored submission, checking that it reproduces exactly the same score
+///
+/// This is how disputes over the leaderboard are settled. It only works for races run with
+/// [deterministic](ExecLimits::deterministic) limits, where only the step limits can change how
+/// the race goes. A replay that runs out of wall-clock time can't be checked either way.
+pub fn verify_replay(
+    source: &Source,
+    track: &'static Racetrack,
+    limits: ExecLimits,
+    expected: &Score,
+) -> Result<(), String> {
+    if !limits.deterministic {
+        return Err("replays can only be verified with deterministic limits".to_owned());
+    }
+
+    let code = Code::from_source(source).map_err(|e| e.to_string())?;
+    let (score, _, _) = Simulation::new(code, track, limits)?
+        .simulate()
+        .map_err(|e| format!("replay was abandoned, so it couldn't be checked: {}", e))?;
+
+    if !score.is_identical(expected) {
+        return Err(format!(
+            "replay gave a different score: expected {:?}, found {:?}",
+            expected, score
+        ));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lazy_static::lazy_static;
+    use std::time::Duration;
+
+    lazy_static! {
+        static ref RACETRACK: Racetrack =
+            Racetrack::from_str(include_str!("../default-racetrack.rtk")).unwrap();
+    }
+
+    const LIMITS: ExecLimits = ExecLimits {
+        steps_per_call: 1000,
+        time_per_call: Duration::from_millis(50),
+        time_per_race: Duration::from_secs(1),
+        memory_cells: 10,
+        deterministic: true,
+    };
+
+    // A simulation with the standard car going straight ahead at `speed` on `surface`
+    fn simulation(speed: f32, surface: Surface) -> Simulation {
+        let code = Code::from_str("acc = 0").unwrap();
+        let mut sim = Simulation::new(code, &RACETRACK, LIMITS).unwrap();
+        sim.car.speed = speed;
+        sim.car.surface = surface;
+        sim.car.slip = Angle::default();
+        sim
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-5,
+            "expected {}, got {}",
+            expected,
+            actual
+        );
+    }
+
+    #[test]
+    fn aerodynamic_drag() {
+        // 0.005 * 8^2 from the air, and 0.01 from rolling on tarmac
+        assert_close(simulation(8.0, Surface::Tarmac).car.resistance(), 0.33);
+        assert_close(simulation(4.0, Surface::Tarmac).car.resistance(), 0.09);
+
+        // Coasting, the car only slows down by the resistance
+        let sim = simulation(8.0, Surface::Tarmac);
+        assert_close(sim.speed_after_step(0.0), 8.0 - 0.33 * STEP);
+    }
+
+    #[test]
+    fn rolling_resistance() {
+        // Gravel has 12 times the rolling resistance of tarmac, on top of 0.005 * 2^2 from the air
+        assert_close(simulation(2.0, Surface::Tarmac).car.resistance(), 0.03);
+        assert_close(simulation(2.0, Surface::Gravel).car.resistance(), 0.14);
+
+        // A car that's nearly stopped is only slowed down to a stop, not pushed backwards
+        assert_close(simulation(0.001, Surface::Gravel).car.resistance(), 0.001);
+        assert_close(simulation(0.0, Surface::Gravel).car.resistance(), 0.0);
+    }
+
+    #[test]
+    fn braking() {
+        // At half of its max speed, the standard car brakes at 2 * 0.5 = 1 unit per tick per tick,
+        // on top of 0.005 * 25 + 0.01 resistance
+        let sim = simulation(5.0, Surface::Tarmac);
+        assert_close(sim.speed_after_step(-1.0), 5.0 - 1.135 * STEP);
+        assert_close(sim.speed_after_step(-0.5), 5.0 - 0.635 * STEP);
+        // Pressing harder than fully is the same as pressing fully
+        assert_close(sim.speed_after_step(-3.0), 5.0 - 1.135 * STEP);
+    }
+
+    #[test]
+    fn braking_never_reverses() {
+        let mut sim = simulation(1.0, Surface::Tarmac);
+        sim.car.brake_power = 50.0;
+        assert_eq!(sim.speed_after_step(-1.0), 0.0);
+
+        assert_eq!(simulation(0.0, Surface::Tarmac).speed_after_step(-1.0), 0.0);
+        assert_eq!(simulation(0.0, Surface::Gravel).speed_after_step(0.0), 0.0);
+    }
+
+    #[test]
+    fn top_speed() {
+        // However powerful the engine, it can't take the car past its top speed
+        let mut sim = simulation(9.9, Surface::Tarmac);
+        sim.car.engine_power = 100.0;
+        sim.car.gear = NUM_GEARS;
+        assert_close(sim.speed_after_step(1.0), 10.0);
+
+        // On grass, the top speed is 60% of the car's max speed. A car that arrives going faster
+        // than that can't accelerate, but it's only slowed down by the resistance (which is
+        // 0.005 * 8^2 + 0.05)
+        let mut sim = simulation(8.0, Surface::Grass);
+        sim.car.engine_power = 100.0;
+        assert_close(sim.speed_after_step(1.0), 8.0 - 0.37 * STEP);
     }
 }
@@ -1,7 +1,9 @@
-//! Wrapper module for the [`Point`] type
+//! Wrapper module for the [`Point`] and [`Angle`] types
 
+use super::strict;
 use serde::Serialize;
-use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Sub, SubAssign};
+use std::f32::consts::PI;
+use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
 /// An (x, y) pair, used to represent points within the region allocated to the racetrack
 // TODO - added debug for ex result
@@ -27,22 +29,35 @@ impl Point {
             ..self
         }
     }
+    /// The z-component of the cross product of the two points as vectors. This is positive if
+    /// `other` is anticlockwise from `self`
+    pub fn cross(self, other: Point) -> f32 {
+        self.x * other.y - self.y * other.x
+    }
+
+    /// The dot product of the two points as vectors
+    pub fn dot(self, other: Point) -> f32 {
+        self.x * other.x + self.y * other.y
+    }
+
     // Gives euclidean distance from point to (0,0)
     pub fn length(&self) -> f32 {
         (self.x*self.x + self.y*self.y).sqrt()
     }
 
-    // TODO - Check I've got this the right way around
-    // ^ Checked by @sharnoff - looks good
-
-    // Produces a new point radius away from the origin at an angle of angle anticlockwise from +ive y
-    pub fn new_polar(radius : f32, angle : f32) -> Point{
+    // Produces a new point radius away from the origin at an angle of angle anticlockwise from +ive x
+    pub fn new_polar(radius : f32, angle : Angle) -> Point{
         Point {
             x: angle.cos() * radius,
             y: angle.sin() * radius,
         }
 
     }
+
+    /// The direction of the point from (0,0), anticlockwise from the positive x direction
+    pub fn angle(self) -> Angle {
+        Angle::from_radians(strict::atan2(self.y, self.x))
+    }
 }
 
 impl Add for Point {
@@ -79,6 +94,17 @@ impl SubAssign for Point {
     }
 }
 
+impl Neg for Point {
+    type Output = Point;
+
+    fn neg(self) -> Point {
+        Point {
+            x: -self.x,
+            y: -self.y,
+        }
+    }
+}
+
 // Scalar multiplication
 impl Mul<f32> for Point {
     type Output = Point;
@@ -113,3 +139,120 @@ impl DivAssign<f32> for Point {
         *self = *self / rhs;
     }
 }
+
+/// An angle, anticlockwise from the positive x direction (or between two directions)
+///
+/// Angles can only be created with [`Angle::from_radians`] or [`Angle::from_degrees`], and only read
+/// with [`Angle::radians`] or [`Angle::degrees`], so there's never any question of which units a
+/// value is in. They're stored (and serialized, as a plain number) in radians.
+#[derive(Copy, Clone, Debug, Default, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Angle(f32);
+
+impl Angle {
+    /// Produces an angle from a value in radians
+    pub fn from_radians(radians: f32) -> Self {
+        Angle(radians)
+    }
+
+    /// Produces an angle from a value in degrees
+    pub fn from_degrees(degrees: f32) -> Self {
+        Angle(degrees.to_radians())
+    }
+
+    /// The size of the angle in radians
+    pub fn radians(self) -> f32 {
+        self.0
+    }
+
+    /// The size of the angle in degrees
+    pub fn degrees(self) -> f32 {
+        self.0.to_degrees()
+    }
+
+    /// Produces the same angle, normalised to be between -pi (exclusive) and pi (inclusive)
+    pub fn normalised(self) -> Self {
+        let a = (self.0 + PI).rem_euclid(2.0 * PI) - PI;
+        // `rem_euclid` gives values in [0, 2pi), so this would be -pi for angles that should be pi
+        if a <= -PI {
+            Angle(a + 2.0 * PI)
+        } else {
+            Angle(a)
+        }
+    }
+
+    pub fn sin(self) -> f32 {
+        strict::sin(self.0)
+    }
+
+    pub fn cos(self) -> f32 {
+        strict::cos(self.0)
+    }
+
+    pub fn abs(self) -> Self {
+        Angle(self.0.abs())
+    }
+
+    /// Restricts the angle to be between `min` and `max`
+    pub fn clamp(self, min: Angle, max: Angle) -> Self {
+        Angle(self.0.max(min.0).min(max.0))
+    }
+
+    /// Returns the smaller of the two angles
+    pub fn min(self, other: Angle) -> Self {
+        Angle(self.0.min(other.0))
+    }
+}
+
+impl Add for Angle {
+    type Output = Angle;
+
+    fn add(self, rhs: Angle) -> Angle {
+        Angle(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Angle {
+    fn add_assign(&mut self, rhs: Angle) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Angle {
+    type Output = Angle;
+
+    fn sub(self, rhs: Angle) -> Angle {
+        Angle(self.0 - rhs.0)
+    }
+}
+
+impl SubAssign for Angle {
+    fn sub_assign(&mut self, rhs: Angle) {
+        *self = *self - rhs;
+    }
+}
+
+impl Neg for Angle {
+    type Output = Angle;
+
+    fn neg(self) -> Angle {
+        Angle(-self.0)
+    }
+}
+
+// Scalar multiplication
+impl Mul<f32> for Angle {
+    type Output = Angle;
+
+    fn mul(self, rhs: f32) -> Angle {
+        Angle(self.0 * rhs)
+    }
+}
+
+impl Div<f32> for Angle {
+    type Output = Angle;
+
+    fn div(self, rhs: f32) -> Angle {
+        Angle(self.0 / rhs)
+    }
+}
@@ -8,9 +8,11 @@
 //! This module doesn't perform any interaction with user-submitted code. Currently-running
 //! simulations are represented by the [`Simulation`] type, and are updated there.
 
-use super::Point;
+use super::smoothing;
+use super::track_header::TrackHeader;
+use super::{Angle, CarSetup, Effect, Footprint, Point, SetupLimits, Surface};
 use serde::Serialize;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 /// In-memory representation of a racetrack
 ///
@@ -24,6 +26,11 @@ use std::collections::HashSet;
 /// might have intersected with.
 #[derive(Clone)]
 pub struct Racetrack {
+    /// The name of the track, if it was given one
+    pub name: Option<String>,
+    /// The person who made the track, if they were given
+    pub author: Option<String>,
+
     /// The height, in tiles of the racetrack. Equal to `grid.len()`
     pub height: usize,
     /// The width, in tiles of the racetrack. Equal to `grid[i].len()` for all `i`
@@ -37,35 +44,58 @@ pub struct Racetrack {
     pub initial_car_state: Car,
 
     /// Two points defining the finish line. Individual tiles have a marker to indicate whether the
-    /// finish line crosses them; this defines the *way* that that happens.
+    /// finish line crosses them; this defines the *way* that that happens. Cars have to cross it
+    /// from the left to the right, looking from the first point towards the second.
     pub finish_line: (Point, Point),
 
+    /// The direction that cars have to cross the finish line in. This is a unit vector at right
+    /// angles to the line, on the side that the car starts facing towards
+    pub finish_normal: Point,
+
+    /// The lines that the car has to cross, in order, before each lap counts. Each one spans the
+    /// track from wall to wall
+    pub checkpoints: Vec<(Point, Point)>,
+
     /// The size of an individual tile
     pub tile_size: f32,
 
     /// The number of laps of the course that need to be performed to win
     pub laps: i32,
+
+    /// The number of ticks the race can last for. A car that hasn't finished by then has
+    /// [timed out](super::Outcome::TimedOut)
+    pub tick_limit: i32,
+
+    /// The limits on the car setups that can be raced on the track
+    pub setup_limits: SetupLimits,
 }
 
-#[derive(Copy, Clone)]
+#[derive(Clone)]
 pub enum GridTile {
-    /// A tile on the edge of the track. `border` gives two points that this edge of the
-    /// racetrack passes through - the side of the border that's inside the racetrack can be
-    /// inferred from the neighboring tiles
+    /// A tile on the edge of the track. `borders` gives the segments of the edge of the racetrack
+    /// that pass through this tile, each of which runs with the racetrack on its left
     ///
     /// For racetrack edges that exactly align with the edge of the tile, the tile with a `Border`
     /// variant is the one contained in the racetrack. A consequence of this is that there cannot
     /// be any racetracks with sections that are a single tile wide.
     ///
-    /// All `Border` tiles were originally defined as part of the racetrack
+    /// Before the borders are smoothed, every `Border` tile was originally defined as part of the
+    /// racetrack, and has exactly one segment. Smoothing can then round the edge off into the
+    /// tiles on either side of it.
     Border {
-        border: (Point, Point),
+        borders: Vec<(Point, Point)>,
         contains_finish_line: bool,
+        surface: Surface,
+        effect: Option<Effect>,
     },
     /// A tile fully contained within the racetrack, not bordering any edge.
     /// `contains_finish_line` indicates whether the finish line crosses through this tile in the
     /// grid
-    Inside { contains_finish_line: bool },
+    Inside {
+        contains_finish_line: bool,
+        surface: Surface,
+        effect: Option<Effect>,
+    },
     /// A tile fully outside of the racetrack, not bordering any edge
     Outside,
 }
@@ -94,6 +124,22 @@ impl GridTile {
             _ => false,
         }
     }
+
+    /// Returns the surface of the tile, if it's part of the racetrack
+    pub fn surface(&self) -> Option<Surface> {
+        match self {
+            Self::Border { surface, .. } | Self::Inside { surface, .. } => Some(*surface),
+            Self::Outside => None,
+        }
+    }
+
+    /// Returns the effect that the tile has on cars that drive onto it, if there is one
+    pub fn effect(&self) -> Option<Effect> {
+        match self {
+            Self::Border { effect, .. } | Self::Inside { effect, .. } => *effect,
+            Self::Outside => None,
+        }
+    }
 }
 
 // Note: the size of the car really only makes sense when compared to the size of the tiles in a
@@ -104,8 +150,11 @@ impl GridTile {
 pub const CAR_LENGTH: f32 = 1.0;
 /// The width of the car
 pub const CAR_WIDTH: f32 = 0.3;
-/// The number of laps of the course required
+/// The number of laps of the course required, unless the track says otherwise
 pub const NUM_LAPS: i32 = 1;
+/// The number of ticks that a race can last for before it ends without the car finishing - a
+/// minute, at 100 ticks per second. Tracks can set their own limit instead
+pub const TICK_LIMIT: i32 = 6000;
 
 /// All of the information about the car at a particular point in time
 // TODO - added debug for ex result
@@ -113,54 +162,217 @@ pub const NUM_LAPS: i32 = 1;
 pub struct Car {
     /// The position of the car
     pub pos: Point,
-    /// The angle the car is facing, anticlockwise from the positive y direction - in radians
-    pub angle: f32,
+    /// The angle the car is facing, anticlockwise from the positive x direction
+    pub angle: Angle,
     /// The current speed, in "unit distance per simulation tick", of the car
     pub speed: f32,
+    /// The slip angle of the car: the angle from the direction the car is facing to the direction
+    /// it's actually moving in. This is zero unless the car is sliding
+    pub slip: Angle,
+    /// The gear that the car is in, from 1 to [`NUM_GEARS`]
+    pub gear: u8,
+    /// The speed of the engine, in revolutions per minute. This follows from the car's speed and
+    /// gear, but never drops below idle
+    pub rpm: f32,
+    /// The surface under the middle of the car, which changes its grip, drag and top speed
+    pub surface: Surface,
 
     /// The maximum speed of the car in units per tick
     #[serde(skip)]
     pub max_speed: f32,
+    /// The power of the engine, as a multiple of the standard car's
+    #[serde(skip)]
+    pub engine_power: f32,
+    /// The strength of the brakes, as a multiple of the standard car's
+    #[serde(skip)]
+    pub brake_power: f32,
 //    /// The maximum acceleration of the car
 //    #[serde(skip)]
 //    pub max_acc: f32,
 //    /// The maximum deceleration of the car
 //    #[serde(skip)]
 //    pub max_dec: f32,
-    // The maximum turning speed of the car in degrees per tick
+    // The maximum turning speed of the car, per tick
     #[serde(skip)]
-    pub max_turn: f32,
+    pub max_turn: Angle,
 }
 
 // Arbitrary variables
 const CAR_MAX_SPEED: f32 = 10.0;
 const CAR_MAX_ACC: f32 = 2.0;
 const CAR_MAX_DEC: f32 = 2.0;
+// In degrees per tick
 const CAR_MAX_TURNING_SPEED: f32 = 10.0;
+// The deceleration from air resistance is this multiplied by the square of the speed
+const CAR_DRAG_COEFFICIENT: f32 = 0.005;
+// The constant deceleration from rolling resistance, whenever the car is moving
+const CAR_ROLLING_RESISTANCE: f32 = 0.01;
+// The radius of the tightest circle that the car can drive around
+const CAR_MIN_TURNING_RADIUS: f32 = 2.0;
+// The largest sideways acceleration that the tyres can provide, in units per tick per tick
+const CAR_MAX_LATERAL_ACC: f32 = 0.5;
+// The extra deceleration from the tyres scrubbing while the car slides sideways. This is scaled by
+// the sine of the slip angle, so it's at its largest when the car is moving completely sideways
+const CAR_SLIDING_FRICTION: f32 = 0.3;
+
+/// The number of forward gears in the gearbox
+pub const NUM_GEARS: u8 = 5;
+// The ratio between engine and wheel speed in each gear. Lower gears give more acceleration, but
+// reach the redline at lower speeds
+const GEAR_RATIOS: [f32; NUM_GEARS as usize] = [3.0, 2.0, 1.4, 1.0, 0.8];
+// The engine speed at a car speed of one unit per tick, in a gear ratio of 1. This puts the redline
+// in top gear at exactly `CAR_MAX_SPEED`
+const RPM_PER_UNIT_SPEED: f32 = 1000.0;
+const IDLE_RPM: f32 = 1000.0;
+// The engine's rev limiter cuts all power from here on
+const REDLINE_RPM: f32 = 8000.0;
+// The acceleration from full throttle at peak torque, per unit of gear ratio. In first gear, this
+// gives `CAR_MAX_ACC`
+const CAR_ENGINE_ACC: f32 = CAR_MAX_ACC / 3.0;
+// The torque curve, as a fraction of peak torque at each engine speed. Torque is interpolated
+// linearly in between these points
+#[rustfmt::skip]
+const TORQUE_CURVE: [(f32, f32); 5] = [
+    (IDLE_RPM, 0.6),
+    (3000.0,   0.9),
+    (5000.0,   1.0),
+    (7000.0,   0.85),
+    (REDLINE_RPM, 0.6),
+];
 
 impl Car {
+    /// The acceleration at full throttle in the car's current gear
     pub fn max_acc(&self) -> f32 {
-        (1.0 - self.speed/self.max_speed )*CAR_MAX_ACC
+        self.max_acc_in_gear(self.gear)
+    }
+
+    /// The acceleration at full throttle if the car were in `gear`, at its current speed
+    ///
+    /// This follows the engine's torque curve, multiplied through the gear ratio. There's no
+    /// acceleration at all once the engine reaches the redline.
+    pub fn max_acc_in_gear(&self, gear: u8) -> f32 {
+        let rpm = self.rpm_in_gear(gear);
+        if rpm >= REDLINE_RPM {
+            return 0.0;
+        }
+
+        let ratio = GEAR_RATIOS[gear as usize - 1] * self.final_drive();
+        torque(rpm) * ratio * CAR_ENGINE_ACC * self.engine_power
+    }
+
+    /// The engine speed if the car were in `gear`, at its current speed
+    pub fn rpm_in_gear(&self, gear: u8) -> f32 {
+        let ratio = GEAR_RATIOS[gear as usize - 1] * self.final_drive();
+        (self.speed.abs() * ratio * RPM_PER_UNIT_SPEED).max(IDLE_RPM)
+    }
+
+    // The gearing is stretched to match the car's max speed, so that top gear always reaches the
+    // redline at exactly the max speed
+    fn final_drive(&self) -> f32 {
+        CAR_MAX_SPEED / self.max_speed
+    }
+
+    /// The gear that an automatic gearbox would pick: whichever gives the most acceleration at the
+    /// car's current speed, preferring higher gears when there's nothing in it
+    pub fn best_gear(&self) -> u8 {
+        let mut best = 1;
+        for gear in 2..=NUM_GEARS {
+            if self.max_acc_in_gear(gear) >= self.max_acc_in_gear(best) {
+                best = gear;
+            }
+        }
+        best
     }
 
     pub fn max_dec(&self) -> f32{
-        (self.speed/self.max_speed )*CAR_MAX_DEC
+        (self.speed/self.max_speed )*CAR_MAX_DEC*self.brake_power
+    }
+
+    /// Produces the same car, but with the given setup in place of the standard one
+    ///
+    /// The setup should already have been validated.
+    pub fn with_setup(self, setup: &CarSetup) -> Car {
+        Car {
+            max_speed: CAR_MAX_SPEED * CarSetup::factor(setup.speed),
+            engine_power: CarSetup::factor(setup.acceleration),
+            brake_power: CarSetup::factor(setup.braking),
+            max_turn: Angle::from_degrees(CAR_MAX_TURNING_SPEED * CarSetup::factor(setup.turning)),
+            ..self
+        }
+    }
+
+    /// The deceleration from air resistance, rolling resistance and sliding at the car's current
+    /// speed. Rolling resistance depends on the surface
+    ///
+    /// This is never more than the speed itself, so resistance alone can't make the car go
+    /// backwards.
+    pub fn resistance(&self) -> f32 {
+        if self.speed <= 0.0 {
+            return 0.0;
+        }
+
+        let drag = CAR_DRAG_COEFFICIENT * self.speed * self.speed;
+        let rolling = CAR_ROLLING_RESISTANCE * self.surface.drag();
+        let sliding = CAR_SLIDING_FRICTION * self.slip.sin().abs();
+        (drag + rolling + sliding).min(self.speed)
+    }
+
+    /// The direction that the car is actually moving in
+    pub fn direction(&self) -> Angle {
+        self.angle + self.slip
+    }
+
+    /// The fastest that the car can currently turn, per tick
+    ///
+    /// This is the smaller of two limits: the car's `max_turn`; and the rate of following its
+    /// tightest turning circle, so that it can't turn on the spot. Turning this fast won't
+    /// necessarily change the direction the car is moving in by as much - see
+    /// [`max_grip_rate`](Self::max_grip_rate).
+    pub fn max_yaw_rate(&self) -> Angle {
+        self.max_turn
+            .min(Angle::from_radians(self.speed.abs() / CAR_MIN_TURNING_RADIUS))
     }
 
-    pub fn pos_of_corners(&self) -> Vec<Point>{
+    /// The fastest that the tyres can turn the direction that the car is moving in, per tick
+    ///
+    /// This is limited by the sideways grip of the tyres, so the faster the car is going, the
+    /// slower it is. If the car turns faster than this, it starts to slide. Surfaces other than
+    /// tarmac have less grip.
+    pub fn max_grip_rate(&self) -> Angle {
+        Angle::from_radians(CAR_MAX_LATERAL_ACC * self.surface.grip() / self.speed.abs())
+    }
 
-        // The relative position of the corners of the car (in polar co-ordinates with distance_to_corners as radius and relative_corner_angle as list of angles)
-        let distance_to_corners = (CAR_LENGTH*CAR_LENGTH + CAR_WIDTH*CAR_WIDTH).sqrt()/2.0;
-        let theta = (CAR_WIDTH/CAR_LENGTH).tan();
-        let relative_corner_angles = vec![theta + self.angle, 180.0 - theta + self.angle, 180.0 + theta + self.angle, 360.0 - theta + self.angle];
+    /// The fastest that the engine can drive the car on its current surface
+    pub fn top_speed(&self) -> f32 {
+        self.max_speed * self.surface.max_speed()
+    }
 
-        let mut ret = Vec::with_capacity(4);
-        for angle in relative_corner_angles.iter() {
-            ret.push(Point::new_polar(distance_to_corners, *angle))
+    /// The rectangle that the car covers on the track
+    pub fn footprint(&self) -> Footprint {
+        Footprint {
+            centre: self.pos,
+            heading: self.angle,
+            length: CAR_LENGTH,
+            width: CAR_WIDTH,
         }
+    }
+}
 
-        ret
+// Returns the fraction of peak torque that the engine produces at `rpm`
+fn torque(rpm: f32) -> f32 {
+    let (mut lo, mut hi) = (TORQUE_CURVE[0], TORQUE_CURVE[0]);
+    for &point in TORQUE_CURVE.iter() {
+        hi = point;
+        if point.0 >= rpm {
+            break;
+        }
+        lo = point;
     }
+
+    if hi.0 <= lo.0 {
+        return lo.1;
+    }
+    lo.1 + (hi.1 - lo.1) * (rpm - lo.0) / (hi.0 - lo.0)
 }
 
 // Characters that represent the bounds of the racetrack
@@ -168,6 +380,18 @@ const OUT_OF_BOUNDS_CHAR: char = 'x';
 const IN_BOUNDS_CHAR: char = ' ';
 const START_TILE_CHAR: char = 's';
 const FINISH_LINE_CHAR: char = '*';
+// Characters for tiles in the racetrack with a surface other than tarmac
+const KERB_CHAR: char = '=';
+const GRASS_CHAR: char = ',';
+const GRAVEL_CHAR: char = '.';
+const ICE_CHAR: char = '~';
+// Characters for tiles with an effect. Boost pads and one-way gates point in the direction given by
+// the character: right, left, up or down
+const BOOST_CHARS: [char; 4] = ['>', '<', '^', 'v'];
+const ONE_WAY_GATE_CHARS: [char; 4] = ['R', 'L', 'U', 'D'];
+const OIL_SLICK_CHAR: char = 'o';
+// Checkpoints are numbered from '1' to '9', in the order they have to be crossed
+const MAX_CHECKPOINTS: u32 = 9;
 
 /// The original representation of a tile, as it was parsed
 #[derive(Copy, Clone)]
@@ -176,6 +400,12 @@ enum TileRepr {
     OutOfBounds,
     Start,
     FinishLine,
+    /// Part of the checkpoint with the given number, starting from 1
+    Checkpoint(usize),
+    /// Part of the racetrack, with a surface other than tarmac
+    Surface(Surface),
+    /// Part of the racetrack, with an effect on cars that drive onto it
+    Effect(Effect),
 }
 
 impl TileRepr {
@@ -186,17 +416,63 @@ impl TileRepr {
             IN_BOUNDS_CHAR => Some(Self::InBounds),
             START_TILE_CHAR => Some(Self::Start),
             FINISH_LINE_CHAR => Some(Self::FinishLine),
-            _ => None,
+            KERB_CHAR => Some(Self::Surface(Surface::Kerb)),
+            GRASS_CHAR => Some(Self::Surface(Surface::Grass)),
+            GRAVEL_CHAR => Some(Self::Surface(Surface::Gravel)),
+            ICE_CHAR => Some(Self::Surface(Surface::Ice)),
+            OIL_SLICK_CHAR => Some(Self::Effect(Effect::OilSlick)),
+            _ if BOOST_CHARS.contains(&c) => Some(Self::Effect(Effect::Boost {
+                direction: char_direction(&BOOST_CHARS, c),
+            })),
+            _ if ONE_WAY_GATE_CHARS.contains(&c) => Some(Self::Effect(Effect::OneWayGate {
+                direction: char_direction(&ONE_WAY_GATE_CHARS, c),
+            })),
+            _ => match c.to_digit(10) {
+                Some(n) if (1..=MAX_CHECKPOINTS).contains(&n) => Some(Self::Checkpoint(n as usize)),
+                _ => None,
+            },
         }
     }
 
     /// Returns whether the tile represents a regiont that's requested to be part of the racetrack
     fn is_part_of_racetrack(&self) -> bool {
         match self {
-            Self::InBounds | Self::Start | Self::FinishLine => true,
+            Self::InBounds
+            | Self::Start
+            | Self::FinishLine
+            | Self::Checkpoint(_)
+            | Self::Surface(_)
+            | Self::Effect(_) => true,
             Self::OutOfBounds => false,
         }
     }
+
+    /// Returns the surface of the tile. Anything that isn't explicitly another surface is tarmac
+    fn surface(&self) -> Surface {
+        match self {
+            Self::Surface(s) => *s,
+            _ => Surface::Tarmac,
+        }
+    }
+
+    /// Returns the tile's effect, if it has one
+    fn effect(&self) -> Option<Effect> {
+        match self {
+            Self::Effect(e) => Some(*e),
+            _ => None,
+        }
+    }
+}
+
+// Returns the direction given by `c`, from a set of characters for right, left, up and down
+fn char_direction(chars: &[char; 4], c: char) -> Angle {
+    let degrees = match chars.iter().position(|&d| d == c) {
+        Some(0) => 0.0,
+        Some(1) => 180.0,
+        Some(2) => 90.0,
+        _ => 270.0,
+    };
+    Angle::from_degrees(degrees)
 }
 
 /// A representation of the grid description that we parse to create a `Racetrack`
@@ -215,8 +491,9 @@ impl InitialGrid {
     ///
     /// The returned tuple is `(width, grid)`, where all of the rows in `grid` have length equal to
     /// `width`. The ordering of the rows has already been reversed, so that `grid[0]` occurs at
-    /// the bottom of the string.
-    fn from_str(input: &str) -> Result<Self, String> {
+    /// the bottom of the string. `first_line` is the line of the whole track description that the
+    /// grid starts on, so that errors can point to the right place.
+    fn from_str(input: &str, first_line: usize) -> Result<Self, String> {
         let mut chars = input.chars().peekable();
 
         // A helper macro to make the parsing a bit easier
@@ -261,21 +538,25 @@ impl InitialGrid {
 
         while chars.peek() != Some(&'+') {
             // Each row is constructed by a beginning pipe ('|'), exactly `width` characters in
-            // { 'x', ' ', 's', '*' }, and a trailing pipe.
-            consume_next!('|'; || format!("expected row {} to start with '|'", rows.len() + 2));
+            // { 'x', ' ', 's', '*' }, a surface, an effect or a checkpoint number, and a trailing
+            // pipe.
+            let line_no = first_line + rows.len() + 1;
+            consume_next!('|'; || format!("expected line {} to start with '|'", line_no));
 
             // Construct the row as we're parsing
             let mut row = Vec::with_capacity(width);
             for _ in 0..width {
                 let tile_repr = chars.next().and_then(TileRepr::from_char).ok_or_else(|| {
                     format!(
-                        "expected one of '{}', '{}', '{}', or '{}' at {:row$}:{:col$}",
+                        "expected one of '{}', '{}', '{}', '{}', a surface, an effect, or a \
+                        checkpoint number at {row}:{col}",
                         OUT_OF_BOUNDS_CHAR,
                         IN_BOUNDS_CHAR,
                         START_TILE_CHAR,
                         FINISH_LINE_CHAR,
-                        // Adding two means that these are indexed starting from 1
-                        row = rows.len() + 2,
+                        // Columns are indexed from 1, and the pipe at the start of the line is
+                        // column 1
+                        row = line_no,
                         col = row.len() + 2,
                     )
                 })?;
@@ -294,8 +575,10 @@ impl InitialGrid {
             // Each *internal* row ends with a pipe and trailing newline
             consume_next!(@str "|\n"; || "expected trailing pipe ('|') at end of inner row");
         }
-        // As promised above, we reverse the ordering of the rows
+        // As promised above, we reverse the ordering of the rows. The start tile was found
+        // counting from the top, so it needs to be flipped to match
         rows.reverse();
+        let start_coords = start_coords.map(|(x, y)| (x, rows.len() - 1 - y));
 
         // We're expecting the bottom line to be the same as the top:
         // +2 for the '+' on each side
@@ -324,14 +607,13 @@ impl InitialGrid {
 
 impl Racetrack {
     /// Parses a `Racetrack` description from a string
+    ///
+    /// The description can start with a [header](TrackHeader) giving the race's settings, before
+    /// the grid itself.
     pub fn from_str(input: &str) -> Result<Self, String> {
-        let init_grid = InitialGrid::from_str(input)?;
-
-        // For now, we'll use the same tile size everywhere. There probably isn't an immediate need
-        // to configure it on a course-by-course basis later; most maps will probably be a similar
-        // size.
-        let tile_size = 2.0_f32;
-        Self::make(init_grid, tile_size)
+        let (header, grid, first_line) = TrackHeader::parse(input)?;
+        let init_grid = InitialGrid::from_str(grid, first_line)?;
+        Self::make(init_grid, header)
     }
 
     /// Constructs the `Racetrack`, provided a representation of the grid the user described
@@ -340,28 +622,38 @@ impl Racetrack {
     /// that we might need to do to make the walls of the racetrack nicer, which is entirely a
     /// "best effort" sort of implementation. The constraints on that are pretty loose, so it
     /// should be fairly possible to improve.
-    fn make(initial_grid: InitialGrid, tile_size: f32) -> Result<Self, String> {
+    fn make(initial_grid: InitialGrid, header: TrackHeader) -> Result<Self, String> {
+        let tile_size = header.tile_size;
+
         // We start the car at the center of the tile, so we need to add 0.5 for its center
         let start_car_pos = Point {
-            x: initial_grid.start_tile.0 as f32 + 0.5 * tile_size,
-            y: initial_grid.start_tile.1 as f32 + 0.5 * tile_size,
+            x: (initial_grid.start_tile.0 as f32 + 0.5) * tile_size,
+            y: (initial_grid.start_tile.1 as f32 + 0.5) * tile_size,
         };
 
         let initial_car_state = Car {
             pos: start_car_pos,
-            // Currently, the car will always start pointing upwards. This could be something we'd
-            // like to configure in the future, but it's not necessary yet.
-            angle: 180_f32,
+            // By default, this points down towards the finish line. It's up to the track to make
+            // sure that still makes sense if it's been changed
+            angle: header.start_angle,
             // The car always starts at a standstill - another thing that could be changed but
             // probably doesn't need to be
             speed: 0_f32,
+            slip: Angle::default(),
+            gear: 1,
+            rpm: IDLE_RPM,
+            // The start tile is always tarmac
+            surface: Surface::Tarmac,
             max_speed: CAR_MAX_SPEED,
-            max_turn: CAR_MAX_TURNING_SPEED,
+            engine_power: 1.0,
+            brake_power: 1.0,
+            max_turn: Angle::from_degrees(CAR_MAX_TURNING_SPEED),
         };
 
         let width = initial_grid.width;
         let height = initial_grid.rows.len();
-        let laps = NUM_LAPS;
+        let laps = header.laps;
+        let tick_limit = header.tick_limit;
 
         // It turns out to be useful to have some way of referring to directions. We'll use this at
         // a couple points later.
@@ -457,6 +749,8 @@ impl Racetrack {
         // While we're iterating over the entire grid, we'll store the tiles representing the
         // finish line for later, just so that we won't have to search for them.
         let mut finish_line_tiles: HashSet<(usize, usize)> = HashSet::new();
+        // The same goes for the checkpoints, which are indexed by their number
+        let mut checkpoint_tiles: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
 
         for (y, row) in initial_grid.rows.iter().enumerate() {
             // The row to be added to the produced grid
@@ -471,8 +765,12 @@ impl Racetrack {
                 }
 
                 // Record if it's part of the finish line
-                if let TileRepr::FinishLine = tile {
-                    finish_line_tiles.insert((x, y));
+                match tile {
+                    TileRepr::FinishLine => {
+                        finish_line_tiles.insert((x, y));
+                    }
+                    TileRepr::Checkpoint(n) => checkpoint_tiles.entry(*n).or_default().push((x, y)),
+                    _ => (),
                 }
 
                 // Determine the set of bordering tiles that are "outside" the racetrack
@@ -500,6 +798,8 @@ impl Racetrack {
                     // when we construct the finish line.
                     grid_row.push(GridTile::Inside {
                         contains_finish_line: false,
+                        surface: tile.surface(),
+                        effect: tile.effect(),
                     });
                     continue;
                 }
@@ -524,6 +824,11 @@ impl Racetrack {
                         n_border_outside += 1;
                     }
                 }
+                // Going clockwise wraps around from left back to up, so up is the more clockwise
+                // of those two
+                if up_outside && left_outside {
+                    most_clockwise = Up;
+                }
 
                 // The bottom-left corner
                 let bot_left = Point {
@@ -535,13 +840,15 @@ impl Racetrack {
                 let top_right = top_left.add_x(tile_size);
 
                 // If we have only one bordering tile outside the racetrack, it must be at the
-                // direction given by `most_clockwise`
+                // direction given by `most_clockwise`. Either way, the points are ordered so that
+                // the racetrack is on the left of the line between them, which is what lets us
+                // join the borders up into smooth walls later.
 
                 let border_points = if n_border_outside == 1 {
                     match most_clockwise {
-                        Up => (top_left, top_right),
+                        Up => (top_right, top_left),
                         Down => (bot_left, bot_right),
-                        Left => (bot_left, top_left),
+                        Left => (top_left, bot_left),
                         Right => (bot_right, top_right),
                     }
                 } else {
@@ -549,114 +856,173 @@ impl Racetrack {
                     // opposite corners.
                     match most_clockwise {
                         // Covering left & up
-                        Up => (bot_left, top_right),
+                        Up => (top_right, bot_left),
                         // Covering up & right
-                        Right => (top_left, bot_right),
+                        Right => (bot_right, top_left),
                         // Covering right & down
-                        Down => (top_right, bot_left),
+                        Down => (bot_left, top_right),
                         // Covering down & left
-                        Left => (bot_right, top_left),
+                        Left => (top_left, bot_right),
                     }
                 };
 
                 grid_row.push(GridTile::Border {
-                    border: border_points,
+                    borders: vec![border_points],
                     contains_finish_line: false,
+                    surface: tile.surface(),
+                    effect: tile.effect(),
                 });
             }
 
             grid.push(grid_row);
         }
 
-        // --- TODO: Smoothing ---
+        // Smoothing:
+        //
+        // The borders that we've made so far only ever run along the edges of tiles or diagonally
+        // across them, so every corner of the track is a staircase. Smoothing joins them up into
+        // walls and rounds those off into curves - see the `smoothing` module for how.
         //
-        // This actually isn't implemented yet! There's already *some* that exists purely because
-        // of the constraints of `GridTile::Border`, but there should be enough information here to
-        // eventually do it in a way that makes sense. The tracking issue for this is here:
-        //   https://github.com/RiverNewbury/AIRacingGame/issues/1
+        // The walls around the start tile, the finish line and the checkpoints are left exactly
+        // where they are, so that the car doesn't start touching a wall and the lines still reach
+        // all the way across the track.
+        let mut pinned = HashSet::new();
+        let fixed_tiles = finish_line_tiles
+            .iter()
+            .chain(checkpoint_tiles.values().flatten())
+            .chain(std::iter::once(&initial_grid.start_tile));
+        for &(x, y) in fixed_tiles {
+            pinned.extend([(x, y), (x + 1, y), (x, y + 1), (x + 1, y + 1)].iter().copied());
+        }
+        smoothing::smooth_borders(&mut grid, &pinned, tile_size);
 
         // Constraint 3:
         //
-        // The ideal implementation here would allow the finish line to be flexible, such that we
-        // generate the it as the only line that passes through all of the finish line tiles (and
-        // no additional ones). We'd take the finish line tiles as something like:
+        // The finish line is the only straight line that passes through all of the finish line
+        // tiles, and no other tiles between the walls on either side of them. That lets it go
+        // across the track at any angle, with finish line tiles like:
         //    ╔═══════╗    ╔═══╗
         //    ║**     ║    ║*  ║
         //    ║  ***  ║ or ║ * ║
         //    ║     **║    ║  *║
         //    ╚═══════╝    ╚═══╝
-        // This sort of thing is *really* hard though, so we aren't doing that yet.
-        // (TODO: Proper finish line)
-        //
-        // Currently, we just take the finish line as a horizontal line going through the middle of
-        // the starting tile, corresponding to how the car starts facing upwards.
-
-        let start_row = initial_grid.start_tile.1;
-        let start_col = initial_grid.start_tile.0;
-
-        // The pair of points that define the finish line. For now, this is just the middle of the
-        // left and right sides of the starting tile.
-        let finish_line = (
-            start_car_pos.add_x(-0.5 * tile_size),
-            start_car_pos.add_x(0.5 * tile_size),
-        );
-
-        // We need to make sure that all of the finish-line tiles in the graph are accounted for in
-        // this area of the graph.
-        let mut num_finish_tiles_accounted_for = 0;
-
-        // Go left of the starting position:
-        for tile in grid[start_row][..start_col].iter_mut().rev() {
-            match tile {
-                GridTile::Border {
-                    contains_finish_line,
-                    ..
-                }
-                | GridTile::Inside {
-                    contains_finish_line,
-                } => *contains_finish_line = true,
-                // We're only going as far as the border of the track in this direction; if we
-                // simply `continue`d, it would be possible to count the finish line on both sides
-                // of a loop -- something we don't want to do.
-                GridTile::Outside => break,
-            }
-
-            num_finish_tiles_accounted_for += 1;
-        }
+        // The start tile can be part of the line too. If it isn't, it has to be right next to it,
+        // so that the car starts just behind the line. Which way the line has to be crossed comes
+        // from the way that the car starts facing.
+        let FinishLine {
+            line: finish_line,
+            normal: finish_normal,
+            tiles: line_tiles,
+        } = finish_line(
+            &finish_line_tiles,
+            initial_grid.start_tile,
+            &is_part_of_racetrack,
+            header.start_angle,
+            tile_size,
+        )
+        .map_err(|e| format!("malformed finish line; {}", e))?;
 
-        // And then go to the right:
-        for tile in grid[start_row][start_col + 1..].iter_mut() {
+        for &(x, y) in line_tiles.iter() {
             #[rustfmt::skip]
-            match tile {
+            match &mut grid[y][x] {
                 GridTile::Border { contains_finish_line, ..  }
-                | GridTile::Inside { contains_finish_line } => *contains_finish_line = true,
-                GridTile::Outside => break,
+                | GridTile::Inside { contains_finish_line, .. } => *contains_finish_line = true,
+                // The line stops at the walls, so it never reaches outside the track
+                GridTile::Outside => (),
             };
-
-            num_finish_tiles_accounted_for += 1;
         }
 
-        // Finally, check that all of the finish line tiles in the parsed representation were
-        // accounted for in the horizontal line here.
-        if num_finish_tiles_accounted_for != finish_line_tiles.len() {
-            return Err(
-                "malformed finish line; should span the track horizontally from the start tile"
-                    .to_owned(),
-            );
+        // Constraint 3 also covers the checkpoints. Each checkpoint must be a single straight row
+        // or column of tiles that reaches from one side of the track to the other, so that there's
+        // no way around it. They also have to be numbered without any gaps, so that a car can
+        // always tell which one is next.
+        let mut checkpoints = Vec::with_capacity(checkpoint_tiles.len());
+        for (i, (&n, tiles)) in checkpoint_tiles.iter().enumerate() {
+            if n != i + 1 {
+                return Err(format!("checkpoint {} is missing", i + 1));
+            }
+
+            let line = checkpoint_line(tiles, &is_part_of_racetrack, tile_size)
+                .map_err(|e| format!("malformed checkpoint {}; {}", n, e))?;
+            checkpoints.push(line);
         }
 
         // And then we're done! We just need to return the final `Racetrack`:
         Ok(Racetrack {
+            name: header.name,
+            author: header.author,
             height,
             width,
             grid,
             initial_car_state,
             finish_line,
+            finish_normal,
+            checkpoints,
             tile_size,
             laps,
+            tick_limit,
+            setup_limits: header.setup_limits,
         })
     }
 
+    /// Returns the surface at the given point. Anywhere that isn't part of the racetrack counts as
+    /// tarmac
+    pub fn surface_at(&self, p: Point) -> Surface {
+        let (x, y) = (p.x / self.tile_size, p.y / self.tile_size);
+        if x < 0.0 || y < 0.0 {
+            return Surface::Tarmac;
+        }
+
+        self.grid
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .and_then(GridTile::surface)
+            .unwrap_or_default()
+    }
+
+    /// Returns the effect of every tile that the footprint overlaps
+    pub fn effects_touching(&self, footprint: &Footprint) -> Vec<Effect> {
+        let corners = footprint.corners();
+        let (mut min, mut max) = (corners[0], corners[0]);
+        for c in corners.iter() {
+            min = Point {
+                x: min.x.min(c.x),
+                y: min.y.min(c.y),
+            };
+            max = Point {
+                x: max.x.max(c.x),
+                y: max.y.max(c.y),
+            };
+        }
+
+        // Any tile that the footprint overlaps has to be in its bounding box
+        let index =
+            |v: f32, len: usize| (v / self.tile_size).floor().max(0.0).min(len as f32) as usize;
+        let (x_min, x_max) = (index(min.x, self.width), index(max.x, self.width));
+        let (y_min, y_max) = (index(min.y, self.height), index(max.y, self.height));
+
+        let mut effects = Vec::new();
+        for (y, row) in self.grid.iter().enumerate().take(y_max + 1).skip(y_min) {
+            for (x, tile) in row.iter().enumerate().take(x_max + 1).skip(x_min) {
+                let effect = match tile.effect() {
+                    Some(e) => e,
+                    None => continue,
+                };
+
+                let tile_min = Point {
+                    x: x as f32 * self.tile_size,
+                    y: y as f32 * self.tile_size,
+                };
+                let tile_max = tile_min.add_x(self.tile_size).add_y(self.tile_size);
+                if footprint.overlaps_box(tile_min, tile_max) {
+                    effects.push(effect);
+                }
+            }
+        }
+
+        effects
+    }
+
     /// Produces a reference to the tile containing the given point
     ///
     /// ## Panics
@@ -666,3 +1032,218 @@ impl Racetrack {
         &self.grid[p.y as usize][p.x as usize]
     }
 }
+
+// Returns the line through the middle of a checkpoint's tiles, running from wall to wall
+//
+// The tiles are given by their (x, y) indexes in `is_part_of_racetrack`, in the order that they
+// were found - so tiles in the same row are sorted by `x`, and rows are sorted by `y`.
+fn checkpoint_line(
+    tiles: &[(usize, usize)],
+    is_part_of_racetrack: &[Vec<bool>],
+    tile_size: f32,
+) -> Result<(Point, Point), String> {
+    let (first, last) = (tiles[0], tiles[tiles.len() - 1]);
+    let is_outside = |x: Option<usize>, y: Option<usize>| match (x, y) {
+        (Some(x), Some(y)) => !is_part_of_racetrack
+            .get(y)
+            .and_then(|row| row.get(x))
+            .copied()
+            .unwrap_or(false),
+        _ => true,
+    };
+
+    let in_a_row = tiles.iter().all(|t| t.1 == first.1) && last.0 - first.0 + 1 == tiles.len();
+    let in_a_column = tiles.iter().all(|t| t.0 == first.0) && last.1 - first.1 + 1 == tiles.len();
+
+    // A single tile could go either way, but with the track at least two tiles wide, it can't
+    // reach both walls in either direction
+    let (line, ends_outside) = if in_a_row {
+        let y = (first.1 as f32 + 0.5) * tile_size;
+        let line = (
+            Point { x: first.0 as f32 * tile_size, y },
+            Point { x: (last.0 + 1) as f32 * tile_size, y },
+        );
+        let left = is_outside(first.0.checked_sub(1), Some(first.1));
+        let right = is_outside(Some(last.0 + 1), Some(last.1));
+        (line, left && right)
+    } else if in_a_column {
+        let x = (first.0 as f32 + 0.5) * tile_size;
+        let line = (
+            Point { x, y: first.1 as f32 * tile_size },
+            Point { x, y: (last.1 + 1) as f32 * tile_size },
+        );
+        let below = is_outside(Some(first.0), first.1.checked_sub(1));
+        let above = is_outside(Some(last.0), Some(last.1 + 1));
+        (line, below && above)
+    } else {
+        return Err("should be a single unbroken row or column of tiles".to_owned());
+    };
+
+    if !ends_outside {
+        return Err("should span the track from one wall to the other".to_owned());
+    }
+
+    Ok(line)
+}
+
+// A finish line, as worked out by `finish_line`
+struct FinishLine {
+    line: (Point, Point),
+    // The direction that the line has to be crossed in
+    normal: Point,
+    // The tiles that the line passes through
+    tiles: Vec<(usize, usize)>,
+}
+
+// Works out the finish line from the tiles that make it up
+//
+// The line is fitted through the middle of the two finish line tiles that are furthest apart, and
+// then followed out in both directions until it reaches a wall. Everything is worked out in units
+// of tiles, and only scaled by `tile_size` at the end.
+fn finish_line(
+    finish_tiles: &HashSet<(usize, usize)>,
+    start_tile: (usize, usize),
+    is_part_of_racetrack: &[Vec<bool>],
+    start_angle: Angle,
+    tile_size: f32,
+) -> Result<FinishLine, String> {
+    let centre = |(x, y): (usize, usize)| Point {
+        x: x as f32 + 0.5,
+        y: y as f32 + 0.5,
+    };
+    let heading = Point::new_polar(1.0, start_angle);
+
+    // Sorting the tiles means that ties between pairs that are equally far apart are always broken
+    // the same way
+    let mut tiles: Vec<_> = finish_tiles.iter().copied().collect();
+    tiles.sort_unstable();
+    let mut ends = match tiles.first() {
+        Some(&t) => (t, t),
+        None => return Err("there aren't any finish line tiles".to_owned()),
+    };
+    let dist = |(a, b)| (centre(b) - centre(a)).length();
+    for (i, &a) in tiles.iter().enumerate() {
+        for &b in tiles[i + 1..].iter() {
+            if dist((a, b)) > dist(ends) {
+                ends = (a, b);
+            }
+        }
+    }
+
+    let origin = centre(ends.0);
+    let along = if ends.0 == ends.1 {
+        // A single tile doesn't give the line a direction, so it goes straight across the way that
+        // the car starts facing
+        Point {
+            x: -heading.y,
+            y: heading.x,
+        }
+    } else {
+        (centre(ends.1) - origin) / dist(ends)
+    };
+
+    // Every tile that the line passes through, along with how far along the line it enters and
+    // leaves them. These are sorted by where the line enters, so neighbouring tiles in the list
+    // are next to each other on the line
+    let mut crossed = Vec::new();
+    for (y, row) in is_part_of_racetrack.iter().enumerate() {
+        for x in 0..row.len() {
+            if let Some(range) = line_in_tile(origin, along, (x, y)) {
+                crossed.push((range, (x, y)));
+            }
+        }
+    }
+    crossed.sort_unstable_by(|a, b| (a.0).0.total_cmp(&(b.0).0));
+
+    // Follow the line out from the first end in both directions, until it reaches a wall. The
+    // edges of the grid count as walls too
+    let is_inside = |(x, y): (usize, usize)| is_part_of_racetrack[y][x];
+    let first = crossed
+        .iter()
+        .position(|&(_, t)| t == ends.0)
+        .expect("line doesn't pass through the tile it was fitted to");
+    let (mut lo, mut hi) = (first, first);
+    while lo > 0 && is_inside(crossed[lo - 1].1) {
+        lo -= 1;
+    }
+    while hi + 1 < crossed.len() && is_inside(crossed[hi + 1].1) {
+        hi += 1;
+    }
+    let line_tiles: Vec<_> = crossed[lo..=hi].iter().map(|&(_, t)| t).collect();
+
+    // The line has to cover every finish line tile, and nothing else apart from the start tile
+    let covered = line_tiles.iter().filter(|t| finish_tiles.contains(t)).count();
+    let on_start = line_tiles.contains(&start_tile) as usize;
+    if covered != finish_tiles.len() || covered + on_start != line_tiles.len() {
+        return Err(
+            "should be a single straight line of tiles from one wall to the other".to_owned(),
+        );
+    }
+
+    let next_to_start = |&(x, y): &(usize, usize)| {
+        let (sx, sy) = start_tile;
+        x.max(sx) - x.min(sx) <= 1 && y.max(sy) - y.min(sy) <= 1
+    };
+    if !line_tiles.iter().any(next_to_start) {
+        return Err("the start tile should be on it or right next to it".to_owned());
+    }
+
+    // The line is crossed forwards by going the way the car starts facing. To the right of the
+    // line, looking from its first point to its second, has to be forwards, so the ends are
+    // swapped if the car is facing the other way.
+    let right = Point {
+        x: along.y,
+        y: -along.x,
+    };
+    let facing = right.dot(heading);
+    if facing.abs() < 1e-3 {
+        return Err("the car has to start facing across it, not along it".to_owned());
+    }
+
+    let ((enter, _), _) = crossed[lo];
+    let ((_, exit), _) = crossed[hi];
+    let (p1, p2) = (origin + along * enter, origin + along * exit);
+    let (p1, p2) = (p1 * tile_size, p2 * tile_size);
+    let (line, normal) = if facing > 0.0 {
+        ((p1, p2), right)
+    } else {
+        ((p2, p1), -right)
+    };
+
+    Ok(FinishLine {
+        line,
+        normal,
+        tiles: line_tiles,
+    })
+}
+
+// The range of distances along the line from `origin` (going in the direction of `along`) that
+// are inside the tile at `(x, y)`, if the line goes through it at all
+//
+// This is measured in units of tiles. Lines that only just clip the corner of a tile don't count
+// as going through it.
+fn line_in_tile(origin: Point, along: Point, (x, y): (usize, usize)) -> Option<(f32, f32)> {
+    const MIN_OVERLAP: f32 = 1e-3;
+
+    let (mut enter, mut exit) = (f32::NEG_INFINITY, f32::INFINITY);
+    for &(pos, dir, min) in [(origin.x, along.x, x as f32), (origin.y, along.y, y as f32)].iter() {
+        if dir.abs() < f32::EPSILON {
+            // The line is parallel to this pair of sides, so it either stays between them the
+            // whole way or never gets between them at all
+            if pos - min < MIN_OVERLAP || min + 1.0 - pos < MIN_OVERLAP {
+                return None;
+            }
+            continue;
+        }
+
+        let (t1, t2) = ((min - pos) / dir, (min + 1.0 - pos) / dir);
+        enter = enter.max(t1.min(t2));
+        exit = exit.min(t1.max(t2));
+    }
+
+    if exit - enter > MIN_OVERLAP {
+        Some((enter, exit))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,110 @@
+//! Exact ray casting against the walls of a [`Racetrack`]
+//!
+//! Rays are traced through the racetrack's grid one tile at a time (the "DDA" algorithm from
+//! Amanatides & Woo), so only the tiles that the ray actually passes through are ever looked at.
+//! Within each [`Border`](GridTile::Border) tile, the ray is intersected with each of the tile's
+//! border segments directly, which gives the exact distance instead of an approximation.
+
+use super::{Angle, GridTile, Point, Racetrack};
+
+// The tolerance used when checking if an intersection lies within a segment or tile. Without it,
+// rays that pass exactly through the end of a border segment (which happens a lot, because they
+// all start and end on the edges of tiles) could slip through the gap between two segments
+const EPSILON: f32 = 1e-4;
+
+/// Returns the distance from `origin` to the nearest wall in the direction `angle`, up to a maximum
+/// of `range`
+///
+/// Walls are the border segments of the racetrack, along with the edges of any tiles outside of
+/// it (including the edge of the grid itself). A ray that starts outside of the racetrack is
+/// immediately blocked, and gives a distance of zero.
+pub fn distance_to_wall(track: &Racetrack, origin: Point, angle: Angle, range: f32) -> f32 {
+    let dir = Point::new_polar(1.0, angle);
+    let size = track.tile_size;
+
+    let mut x = (origin.x / size).floor() as isize;
+    let mut y = (origin.y / size).floor() as isize;
+
+    // For each axis: the direction we step through the tiles in, the distance along the ray until
+    // it crosses into the next tile, and the distance along the ray between each crossing
+    let axis = |pos: f32, tile: isize, d: f32| {
+        if d > 0.0 {
+            (1, ((tile + 1) as f32 * size - pos) / d, size / d)
+        } else if d < 0.0 {
+            (-1, (tile as f32 * size - pos) / d, -size / d)
+        } else {
+            (0, f32::INFINITY, f32::INFINITY)
+        }
+    };
+    let (step_x, mut next_x, delta_x) = axis(origin.x, x, dir.x);
+    let (step_y, mut next_y, delta_y) = axis(origin.y, y, dir.y);
+
+    // The distance along the ray at which it entered the current tile
+    let mut entered = 0.0_f32;
+
+    while entered < range {
+        let tile = match tile_at(track, x, y) {
+            Some(t) => t,
+            // Leaving the grid counts as hitting a wall
+            None => return entered,
+        };
+        let exited = next_x.min(next_y);
+
+        match tile {
+            GridTile::Outside => return entered,
+            GridTile::Border { borders, .. } => {
+                let nearest = borders
+                    .iter()
+                    .filter_map(|&(p1, p2)| intersect_segment(origin, dir, p1, p2))
+                    .filter(|&t| t <= exited + EPSILON)
+                    .min_by(f32::total_cmp);
+                if let Some(t) = nearest {
+                    return t.min(range);
+                }
+            }
+            GridTile::Inside { .. } => (),
+        }
+
+        if next_x < next_y {
+            x += step_x;
+            entered = next_x;
+            next_x += delta_x;
+        } else {
+            y += step_y;
+            entered = next_y;
+            next_y += delta_y;
+        }
+    }
+
+    range
+}
+
+// Returns the tile at the given indexes, if they're within the grid
+fn tile_at(track: &Racetrack, x: isize, y: isize) -> Option<&GridTile> {
+    if x < 0 || y < 0 {
+        return None;
+    }
+    track.grid.get(y as usize)?.get(x as usize)
+}
+
+// Returns the distance along the ray from `origin` in the direction `dir` at which it crosses the
+// segment from `p1` to `p2`, if it does. The distance is given in multiples of `dir`
+pub(super) fn intersect_segment(origin: Point, dir: Point, p1: Point, p2: Point) -> Option<f32> {
+    let seg = p2 - p1;
+    let denom = dir.cross(seg);
+    // The ray is parallel to the segment. Even if the ray runs along it, it'll be picked up by
+    // the neighbouring segments or the tiles outside the track
+    if denom.abs() < f32::EPSILON {
+        return None;
+    }
+
+    let to_start = p1 - origin;
+    let t = to_start.cross(seg) / denom;
+    let s = to_start.cross(dir) / denom;
+
+    if t >= -EPSILON && (-EPSILON..=1.0 + EPSILON).contains(&s) {
+        Some(t.max(0.0))
+    } else {
+        None
+    }
+}
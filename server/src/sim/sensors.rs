@@ -0,0 +1,121 @@
+//! The sensors that give user code its distances to the wall
+//!
+//! Each submission picks its own [`SensorRig`] in its header: how many rays there are, which way
+//! each of them points, how far they can see, and how noisy their readings are. The rig is part of
+//! the competition, so it's checked against the limits here before a race starts.
+
+use std::f32::consts::PI;
+
+use super::{strict, Angle};
+
+/// The number of rays in the default rig, spaced evenly around the car
+pub const DEFAULT_RAYS: usize = 60;
+/// The maximum number of rays in a rig
+pub const MAX_RAYS: usize = 64;
+/// The maximum distance that a ray can see
+pub const MAX_RANGE: f32 = 100.0;
+/// The maximum standard deviation of the noise on each reading
+pub const MAX_NOISE: f32 = 5.0;
+
+// The seed for the noise on sensor readings. This is fixed so that the same submission always
+// gets the same readings, and therefore the same result
+const NOISE_SEED: u64 = 0x5eed_cafe_f00d_d00d;
+
+/// The layout of the rays used to produce `dist_to_wall`
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorRig {
+    /// The angle of each ray, anticlockwise from the direction the car is facing
+    pub angles: Vec<Angle>,
+    /// The maximum distance that each ray can see. A ray that doesn't hit a wall within this
+    /// distance reads as exactly `range`
+    pub range: f32,
+    /// The standard deviation of the normally-distributed noise added to each reading. Readings
+    /// are always kept between 0 and `range`
+    pub noise: f32,
+}
+
+impl Default for SensorRig {
+    fn default() -> Self {
+        SensorRig::evenly_spaced(DEFAULT_RAYS)
+    }
+}
+
+impl SensorRig {
+    /// Produces a rig with `rays` rays spaced evenly around the car, starting straight ahead, with
+    /// the maximum range and no noise
+    pub fn evenly_spaced(rays: usize) -> Self {
+        SensorRig {
+            angles: (0..rays)
+                .map(|i| Angle::from_radians(i as f32 * 2.0 * PI / rays as f32))
+                .collect(),
+            range: MAX_RANGE,
+            noise: 0.0,
+        }
+    }
+
+    /// Checks that the rig is within the limits allowed in a race
+    pub fn validate(&self) -> Result<(), String> {
+        if self.angles.is_empty() || self.angles.len() > MAX_RAYS {
+            return Err(format!(
+                "sensor error: there must be between 1 and {} rays, but there are {}",
+                MAX_RAYS,
+                self.angles.len()
+            ));
+        }
+        if let Some(a) = self.angles.iter().find(|a| !a.degrees().is_finite()) {
+            return Err(format!(
+                "sensor error: ray angle {} is not finite",
+                a.degrees()
+            ));
+        }
+        if !(self.range > 0.0 && self.range <= MAX_RANGE) {
+            return Err(format!(
+                "sensor error: range must be more than 0 and at most {}, but it's {}",
+                MAX_RANGE, self.range
+            ));
+        }
+        if !(self.noise >= 0.0 && self.noise <= MAX_NOISE) {
+            return Err(format!(
+                "sensor error: noise must be between 0 and {}, but it's {}",
+                MAX_NOISE, self.noise
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// A small, deterministic source of random noise for sensor readings (xorshift64*)
+pub struct Noise {
+    state: u64,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Noise { state: NOISE_SEED }
+    }
+}
+
+impl Noise {
+    /// Produces a number uniformly distributed in (0, 1]
+    pub fn uniform(&mut self) -> f32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        let bits = self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 40;
+        (bits as f32 + 1.0) / (1u64 << 24) as f32
+    }
+
+    /// Applies the rig's noise to a single reading
+    pub fn apply(&mut self, rig: &SensorRig, reading: f32) -> f32 {
+        if rig.noise == 0.0 {
+            return reading;
+        }
+
+        // Box-Muller transform, to get a normally-distributed sample
+        let (u1, u2) = (self.uniform(), self.uniform());
+        let sample = (-2.0 * strict::ln(u1)).sqrt() * strict::cos(2.0 * PI * u2);
+
+        (reading + sample * rig.noise).max(0.0).min(rig.range)
+    }
+}
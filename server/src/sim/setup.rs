@@ -0,0 +1,103 @@
+//! Car setups, chosen by each submission
+//!
+//! Every submission gets the same budget of points to spend on its car, split between top speed,
+//! acceleration, braking and turning. Putting more points into one of them means taking points away
+//! from another, so there's a real choice to make - e.g. a car that corners well but is slow on the
+//! straights. Like the sensor rig, the setup is part of the competition, so it's checked against the
+//! budget before a race starts. Tracks can change the budget, to restrict the cars raced on them.
+
+use serde::Serialize;
+
+/// The total number of points that can be spent on a setup, unless the track says otherwise
+pub const SETUP_BUDGET: u32 = 20;
+/// The maximum number of points that can be spent on a single part of a setup, unless the track
+/// says otherwise
+pub const MAX_SETUP_POINTS: u32 = 10;
+/// The most points that a track can allow, either in total or on a single part. Each point adds
+/// 10% to the standard car, so anything past this would only make cars too fast to simulate
+pub const SETUP_POINTS_CAP: u32 = 100;
+// The number of points spent on each part of the default setup, which gives the standard car
+const DEFAULT_POINTS: u32 = 5;
+
+/// The number of points spent on each part of the car
+///
+/// Each point is worth 10% of the standard car's value, so that spending 5 points on something
+/// gives exactly the standard car, and spending nothing gives half of it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct CarSetup {
+    /// Points spent on top speed. The gearing is stretched to match, so a faster car also
+    /// accelerates more slowly in each gear
+    pub speed: u32,
+    /// Points spent on the power of the engine
+    pub acceleration: u32,
+    /// Points spent on the strength of the brakes
+    pub braking: u32,
+    /// Points spent on how fast the car can turn
+    pub turning: u32,
+}
+
+impl Default for CarSetup {
+    fn default() -> Self {
+        CarSetup {
+            speed: DEFAULT_POINTS,
+            acceleration: DEFAULT_POINTS,
+            braking: DEFAULT_POINTS,
+            turning: DEFAULT_POINTS,
+        }
+    }
+}
+
+/// The limits on how a car setup can spend its points, which each track can set for itself
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SetupLimits {
+    /// The total number of points that can be spent
+    pub budget: u32,
+    /// The maximum number of points that can be spent on a single part
+    pub max_points: u32,
+}
+
+impl Default for SetupLimits {
+    fn default() -> Self {
+        SetupLimits {
+            budget: SETUP_BUDGET,
+            max_points: MAX_SETUP_POINTS,
+        }
+    }
+}
+
+impl CarSetup {
+    /// Checks that the setup is within the track's limits
+    pub fn validate(&self, limits: &SetupLimits) -> Result<(), String> {
+        let parts = [
+            ("speed", self.speed),
+            ("acceleration", self.acceleration),
+            ("braking", self.braking),
+            ("turning", self.turning),
+        ];
+
+        for &(name, points) in parts.iter() {
+            if points > limits.max_points {
+                return Err(format!(
+                    "setup error: at most {} points can be spent on {}, but there are {}",
+                    limits.max_points, name, points
+                ));
+            }
+        }
+
+        // Summed as u64, so that the total can't overflow however many points are asked for
+        let total: u64 = parts.iter().map(|&(_, points)| u64::from(points)).sum();
+        if total > u64::from(limits.budget) {
+            return Err(format!(
+                "setup error: at most {} points can be spent in total, but there are {}",
+                limits.budget, total
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The multiple of the standard car's value given by spending `points` on something
+    pub fn factor(points: u32) -> f32 {
+        0.5 + 0.1 * points as f32
+    }
+}
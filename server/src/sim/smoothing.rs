@@ -0,0 +1,258 @@
+//! Smoothing for the walls of a [`Racetrack`](super::Racetrack)
+//!
+//! Tracks are drawn on a grid, so before smoothing, every wall is made of segments that run along
+//! the edges of tiles or diagonally across them. Smoothing joins those segments up into whole
+//! walls, and then rounds each wall off by repeatedly cutting its corners (Chaikin's algorithm).
+//! Doing that a few times gives a close approximation of the quadratic B-spline that the wall's
+//! corners define, so a staircase turns into a curve that never strays more than about a third of a
+//! tile from the wall that was drawn.
+//!
+//! The smoothed walls are then cut up into the tiles that they pass through. Rounding off a corner
+//! can move the wall into a tile that used to be entirely inside or outside of the track, in which
+//! case that tile becomes a border tile too.
+
+use super::{GridTile, Point, Surface};
+use std::collections::{HashMap, HashSet};
+
+// The number of times that the corners of each wall are cut. Each pass doubles the number of
+// segments in the wall, and gets it closer to a smooth curve
+const SMOOTHING_PASSES: usize = 3;
+
+// The corner of a tile, by its (x, y) index in the grid of corners. The bottom-left corner of the
+// tile at (x, y) is (x, y), and its top-right corner is (x + 1, y + 1)
+type Corner = (usize, usize);
+
+// A single wall of the track, in units of tiles
+struct Wall {
+    points: Vec<Point>,
+    // Whether the wall is a loop. If it is, the last point joins back up to the first
+    closed: bool,
+}
+
+/// Smooths the borders in the grid, which must each have a single segment with the racetrack on
+/// its left
+///
+/// The walls are never moved at any of the `pinned` corners.
+pub fn smooth_borders(grid: &mut [Vec<GridTile>], pinned: &HashSet<Corner>, tile_size: f32) {
+    // We work in units of tiles, so that every segment starts and ends on a corner
+    let corner = |p: Point| {
+        let (x, y) = (p.x / tile_size, p.y / tile_size);
+        (x.round() as usize, y.round() as usize)
+    };
+
+    let mut segments = Vec::new();
+    for row in grid.iter_mut() {
+        for tile in row.iter_mut() {
+            if let GridTile::Border { borders, .. } = tile {
+                segments.extend(borders.drain(..).map(|(p1, p2)| (corner(p1), corner(p2))));
+            }
+        }
+    }
+
+    for wall in join_walls(&segments, pinned).iter() {
+        let points = smooth(wall);
+        for pair in points.windows(2) {
+            add_segment(grid, pair[0], pair[1], tile_size);
+        }
+    }
+
+    // Walls only ever move by a fraction of a tile, so any border tile that's been left without
+    // one must be one that the wall was rounded off out of, into the rest of the track
+    for row in grid.iter_mut() {
+        for tile in row.iter_mut() {
+            if let GridTile::Border {
+                borders,
+                contains_finish_line,
+                surface,
+                effect,
+            } = tile
+            {
+                if borders.is_empty() {
+                    *tile = GridTile::Inside {
+                        contains_finish_line: *contains_finish_line,
+                        surface: *surface,
+                        effect: *effect,
+                    };
+                }
+            }
+        }
+    }
+}
+
+// Joins the segments up into walls, going from one segment to the next wherever one ends at the
+// same corner that the other starts at
+//
+// Walls can only be smoothed through corners where exactly one segment ends and exactly one
+// starts. Anywhere else (like where two walls touch at a corner), and at all of the `pinned`
+// corners, the walls are split up so that they stay where they are.
+fn join_walls(segments: &[(Corner, Corner)], pinned: &HashSet<Corner>) -> Vec<Wall> {
+    let mut starting_at: HashMap<Corner, Vec<usize>> = HashMap::new();
+    let mut num_ending_at: HashMap<Corner, usize> = HashMap::new();
+    for (i, &(start, end)) in segments.iter().enumerate() {
+        starting_at.entry(start).or_default().push(i);
+        *num_ending_at.entry(end).or_default() += 1;
+    }
+
+    let is_fixed = |c: Corner| {
+        pinned.contains(&c)
+            || starting_at.get(&c).map_or(0, Vec::len) != 1
+            || num_ending_at.get(&c).copied().unwrap_or(0) != 1
+    };
+    let to_point = |(x, y): Corner| Point {
+        x: x as f32,
+        y: y as f32,
+    };
+
+    // We go through the walls that start at a fixed corner first. Once they're all done, whatever
+    // is left must be loops without any fixed corners at all
+    let mut used = vec![false; segments.len()];
+    let mut walls = Vec::new();
+    let from_fixed = (0..segments.len()).filter(|&i| is_fixed(segments[i].0));
+
+    for first in from_fixed.chain(0..segments.len()) {
+        if used[first] {
+            continue;
+        }
+
+        let closed = !is_fixed(segments[first].0);
+        let mut points = vec![to_point(segments[first].0)];
+        let mut current = first;
+        loop {
+            used[current] = true;
+            let end = segments[current].1;
+            if is_fixed(end) {
+                points.push(to_point(end));
+                break;
+            }
+
+            // There's exactly one segment starting here. If we've already used it, we've gone all
+            // the way around a loop
+            current = starting_at[&end][0];
+            if used[current] {
+                break;
+            }
+            points.push(to_point(end));
+        }
+
+        walls.push(Wall { points, closed });
+    }
+
+    walls
+}
+
+// Returns the points along the smoothed wall. Loops are given with their first point repeated at
+// the end, and the ends of walls that aren't loops don't move
+fn smooth(wall: &Wall) -> Vec<Point> {
+    let mut points = wall.points.clone();
+
+    for _ in 0..SMOOTHING_PASSES {
+        let n = points.len();
+        let mut cut = Vec::with_capacity(2 * n + 2);
+
+        // Every segment is replaced by the middle half of it, which cuts off the corners between
+        // them
+        let middle_half = |a: Point, b: Point| [a * 0.75 + b * 0.25, a * 0.25 + b * 0.75];
+
+        if wall.closed {
+            for i in 0..n {
+                cut.extend(middle_half(points[i], points[(i + 1) % n]).iter());
+            }
+        } else {
+            cut.push(points[0]);
+            for pair in points.windows(2) {
+                cut.extend(middle_half(pair[0], pair[1]).iter());
+            }
+            cut.push(points[n - 1]);
+        }
+
+        points = cut;
+    }
+
+    if wall.closed {
+        points.push(points[0]);
+    }
+    points
+}
+
+// Adds the part of a wall from `a` to `b` (in units of tiles) to the tiles that it passes through
+fn add_segment(grid: &mut [Vec<GridTile>], a: Point, b: Point, tile_size: f32) {
+    // The fractions of the way along the segment at which it crosses from one tile to the next
+    let mut cuts = vec![0.0, 1.0];
+    for &(from, to) in [(a.x, b.x), (a.y, b.y)].iter() {
+        let mut line = from.min(to).floor() + 1.0;
+        while line < from.max(to) {
+            cuts.push((line - from) / (to - from));
+            line += 1.0;
+        }
+    }
+    cuts.sort_unstable_by(f32::total_cmp);
+
+    for pair in cuts.windows(2) {
+        let (p1, p2) = (a + (b - a) * pair[0], a + (b - a) * pair[1]);
+        let length = (p2 - p1).length();
+        if length < 1e-6 {
+            continue;
+        }
+
+        // The racetrack is on the left of the segment. Nudging the middle of it that way means
+        // that parts of the wall running exactly along the edge of a tile go in the tile on the
+        // racetrack's side, just like they did before smoothing
+        let along = (p2 - p1) / length;
+        let left = Point {
+            x: -along.y,
+            y: along.x,
+        };
+        let middle = (p1 + p2) / 2.0 + left * 1e-3;
+        let (x, y) = (middle.x.floor() as usize, middle.y.floor() as usize);
+        // The edges of the grid are always outside of the track, so this should never happen -
+        // but there's nowhere to put the segment if it does
+        if y >= grid.len() || x >= grid[y].len() {
+            continue;
+        }
+
+        let segment = (p1 * tile_size, p2 * tile_size);
+        let surface = neighbouring_surface(grid, x, y);
+        let tile = &mut grid[y][x];
+        *tile = match tile {
+            GridTile::Border { borders, .. } => {
+                borders.push(segment);
+                continue;
+            }
+            GridTile::Inside {
+                contains_finish_line,
+                surface,
+                effect,
+            } => GridTile::Border {
+                borders: vec![segment],
+                contains_finish_line: *contains_finish_line,
+                surface: *surface,
+                effect: *effect,
+            },
+            // Part of the tile is on the track now, so it takes on the surface of the track next
+            // to it
+            GridTile::Outside => GridTile::Border {
+                borders: vec![segment],
+                contains_finish_line: false,
+                surface,
+                effect: None,
+            },
+        };
+    }
+}
+
+// Returns the surface of the first tile next to the one at (x, y) that's part of the track, or
+// tarmac if none of them are
+fn neighbouring_surface(grid: &[Vec<GridTile>], x: usize, y: usize) -> Surface {
+    let neighbours = [
+        (Some(x), y.checked_add(1)),
+        (x.checked_add(1), Some(y)),
+        (Some(x), y.checked_sub(1)),
+        (x.checked_sub(1), Some(y)),
+    ];
+
+    neighbours
+        .iter()
+        .filter_map(|&(x, y)| grid.get(y?)?.get(x?)?.surface())
+        .next()
+        .unwrap_or_default()
+}
@@ -0,0 +1,175 @@
+//! Strictly-specified maths functions, so that the simulation gives exactly the same results
+//! everywhere
+//!
+//! Basic floating-point arithmetic (`+`, `-`, `*`, `/` and `sqrt`) is exactly specified by IEEE 754,
+//! so it always gives the same result. The standard library's `sin`, `atan2`, `ln` and friends
+//! aren't: they come from the platform's maths library, and can differ in the last bit between
+//! platforms, compilers or even versions of the same library. Over thousands of ticks, those
+//! differences add up to a different race.
+//!
+//! The functions here are built only from basic arithmetic, so their results never change. They're
+//! calculated in `f64` and rounded to `f32` at the end, which makes them as accurate as the
+//! standard versions for all of our purposes.
+
+use std::f64::consts::{FRAC_PI_2, LN_2, PI};
+
+/// The sine of `x`, in radians
+pub fn sin(x: f32) -> f32 {
+    let (quadrant, r) = reduce(x as f64);
+    (match quadrant {
+        0 => sin_poly(r),
+        1 => cos_poly(r),
+        2 => -sin_poly(r),
+        _ => -cos_poly(r),
+    }) as f32
+}
+
+/// The cosine of `x`, in radians
+pub fn cos(x: f32) -> f32 {
+    let (quadrant, r) = reduce(x as f64);
+    (match quadrant {
+        0 => cos_poly(r),
+        1 => -sin_poly(r),
+        2 => -cos_poly(r),
+        _ => sin_poly(r),
+    }) as f32
+}
+
+/// The tangent of `x`, in radians
+pub fn tan(x: f32) -> f32 {
+    let (quadrant, r) = reduce(x as f64);
+    let (s, c) = (sin_poly(r), cos_poly(r));
+    (if quadrant % 2 == 0 { s / c } else { -c / s }) as f32
+}
+
+/// The angle (in radians, between -pi and pi) from the positive x axis to the point `(x, y)`, the
+/// same as [`f32::atan2`]
+pub fn atan2(y: f32, x: f32) -> f32 {
+    let (y, x) = (y as f64, x as f64);
+    if y.is_nan() || x.is_nan() {
+        return f32::NAN;
+    }
+    if x == 0.0 && y == 0.0 {
+        // Matching the standard library, which keeps the signs of the zeros
+        return match (x.is_sign_negative(), y.is_sign_negative()) {
+            (false, false) => 0.0,
+            (false, true) => -0.0,
+            (true, false) => PI as f32,
+            (true, true) => -PI as f32,
+        };
+    }
+
+    // atan(y / x) only covers the right half of the plane, so we measure from whichever axis is
+    // closer to keep the ratio at most 1
+    let a = if x.abs() >= y.abs() {
+        let a = atan(y / x);
+        if x > 0.0 {
+            a
+        } else if !y.is_sign_negative() {
+            a + PI
+        } else {
+            a - PI
+        }
+    } else {
+        let a = -atan(x / y);
+        if y > 0.0 {
+            a + FRAC_PI_2
+        } else {
+            a - FRAC_PI_2
+        }
+    };
+
+    a as f32
+}
+
+/// The natural logarithm of `x`
+pub fn ln(x: f32) -> f32 {
+    let x = x as f64;
+    if x.is_nan() || x < 0.0 {
+        return f32::NAN;
+    }
+    if x == 0.0 {
+        return f32::NEG_INFINITY;
+    }
+    if x.is_infinite() {
+        return f32::INFINITY;
+    }
+
+    // Split `x` into `m * 2^e`, with `m` between sqrt(1/2) and sqrt(2). Every `f32` is a normal
+    // `f64`, so the exponent bits can be read directly
+    let bits = x.to_bits();
+    let mut e = ((bits >> 52) & 0x7ff) as i64 - 1023;
+    let mut m = f64::from_bits((bits & !(0x7ff << 52)) | (1023 << 52));
+    if m > std::f64::consts::SQRT_2 {
+        m /= 2.0;
+        e += 1;
+    }
+
+    // ln(m) = 2 * atanh((m - 1) / (m + 1)), and the series for atanh converges quickly for the
+    // small values that this gives
+    let s = (m - 1.0) / (m + 1.0);
+    let s2 = s * s;
+    let mut term = s;
+    let mut sum = 0.0;
+    for n in 0..14 {
+        sum += term / (2 * n + 1) as f64;
+        term *= s2;
+    }
+
+    (2.0 * sum + e as f64 * LN_2) as f32
+}
+
+// Reduces `x` to `quadrant * pi/2 + r`, with `r` between -pi/4 and pi/4 and `quadrant` between 0
+// and 3
+fn reduce(x: f64) -> (u8, f64) {
+    let k = (x / FRAC_PI_2).round();
+    // `FRAC_PI_2` is slightly off from the real pi/2, so we correct for the difference to keep
+    // `k * pi/2` accurate even for large `k`
+    const FRAC_PI_2_ERROR: f64 = 6.123_233_995_736_766e-17;
+    let r = (x - k * FRAC_PI_2) - k * FRAC_PI_2_ERROR;
+    (k.rem_euclid(4.0) as u8, r)
+}
+
+// The Taylor series for sin, accurate to within `f64` rounding for |r| <= pi/4
+fn sin_poly(r: f64) -> f64 {
+    let r2 = r * r;
+    let mut term = r;
+    let mut sum = 0.0;
+    for n in 1..10 {
+        sum += term;
+        term *= -r2 / ((2 * n) * (2 * n + 1)) as f64;
+    }
+    sum
+}
+
+// The Taylor series for cos, accurate to within `f64` rounding for |r| <= pi/4
+fn cos_poly(r: f64) -> f64 {
+    let r2 = r * r;
+    let mut term = 1.0;
+    let mut sum = 0.0;
+    for n in 1..10 {
+        sum += term;
+        term *= -r2 / ((2 * n - 1) * (2 * n)) as f64;
+    }
+    sum
+}
+
+// The arctangent of `z`, for |z| <= 1
+fn atan(z: f64) -> f64 {
+    // Each application of atan(z) = 2 * atan(z / (1 + sqrt(1 + z^2))) halves the angle. After two,
+    // |z| is at most tan(pi/16), where the Taylor series converges quickly
+    let mut z = z;
+    for _ in 0..2 {
+        z /= 1.0 + (1.0 + z * z).sqrt();
+    }
+
+    let z2 = z * z;
+    let mut term = z;
+    let mut sum = 0.0;
+    for n in 0..14 {
+        sum += term / (2 * n + 1) as f64;
+        term *= -z2;
+    }
+
+    4.0 * sum
+}
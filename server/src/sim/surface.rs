@@ -0,0 +1,56 @@
+//! The surfaces that a track can be made of, and how they affect the car
+//!
+//! Most of a track is tarmac, but track designers can lay down other surfaces to give run-off
+//! areas around corners: going off onto grass or gravel slows the car down rather than ending the
+//! race. The car's physics use whichever surface is under the middle of the car.
+
+use serde::Serialize;
+
+/// The surface of a single tile on the track
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Surface {
+    /// The normal racing surface
+    #[default]
+    Tarmac,
+    /// The raised edge of a corner. Slightly bumpy, but fine to drive over
+    Kerb,
+    /// Grass run-off. Slippery and slow
+    Grass,
+    /// A gravel trap, which slows the car down very quickly
+    Gravel,
+    /// Almost no grip at all, but nothing to slow the car down either
+    Ice,
+}
+
+impl Surface {
+    /// The sideways grip of the tyres on this surface, as a fraction of their grip on tarmac
+    pub fn grip(self) -> f32 {
+        self.properties().0
+    }
+
+    /// The rolling resistance of the surface, as a multiple of tarmac's
+    pub fn drag(self) -> f32 {
+        self.properties().1
+    }
+
+    /// The fastest that the engine can drive the car on this surface, as a fraction of the car's
+    /// max speed. A car that arrives going faster than this isn't slowed down instantly; it just
+    /// can't accelerate until it's below it
+    pub fn max_speed(self) -> f32 {
+        self.properties().2
+    }
+
+    // (grip, drag, max speed) for each surface
+    #[rustfmt::skip]
+    fn properties(self) -> (f32, f32, f32) {
+        match self {
+            //                  grip  drag  max speed
+            Surface::Tarmac => (1.0,  1.0,  1.0),
+            Surface::Kerb =>   (0.8,  2.0,  1.0),
+            Surface::Grass =>  (0.5,  5.0,  0.6),
+            Surface::Gravel => (0.6,  12.0, 0.4),
+            Surface::Ice =>    (0.15, 0.5,  1.0),
+        }
+    }
+}
@@ -0,0 +1,192 @@
+//! Parsing for the settings header at the top of a track description
+//!
+//! A track can start with any number of `key = value` lines before the `+---+` line that begins its
+//! grid, describing everything about the race that isn't the shape of the track:
+//! ```text
+//! name = Figure of Eight
+//! author = River
+//! laps = 3
+//! tile_size = 2.5
+//! # Cars here are limited to 15 points
+//! setup_budget = 15
+//! ```
+//! Blank lines and lines starting with `#` are ignored. Anything that isn't given keeps its
+//! default, so a track without a header is still valid.
+
+use super::racetrack::{CAR_LENGTH, NUM_LAPS, TICK_LIMIT};
+use super::{Angle, SetupLimits, SETUP_POINTS_CAP};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+// The size of each tile, unless the track says otherwise
+const DEFAULT_TILE_SIZE: f32 = 2.0;
+// The direction that the car starts facing, in degrees, unless the track says otherwise. This is
+// pointing down the screen
+const DEFAULT_START_ANGLE: f32 = 270.0;
+
+/// The settings given in a track's header, with defaults for anything not provided
+pub struct TrackHeader {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub laps: i32,
+    pub tile_size: f32,
+    pub start_angle: Angle,
+    pub tick_limit: i32,
+    pub setup_limits: SetupLimits,
+}
+
+impl Default for TrackHeader {
+    fn default() -> Self {
+        TrackHeader {
+            name: None,
+            author: None,
+            laps: NUM_LAPS,
+            tile_size: DEFAULT_TILE_SIZE,
+            start_angle: Angle::from_degrees(DEFAULT_START_ANGLE),
+            tick_limit: TICK_LIMIT,
+            setup_limits: SetupLimits::default(),
+        }
+    }
+}
+
+impl TrackHeader {
+    /// Parses the header from the start of a track description
+    ///
+    /// Along with the header, this returns the rest of the description (starting at the grid) and
+    /// the line number that the grid starts on.
+    pub fn parse(input: &str) -> Result<(TrackHeader, &str, usize), String> {
+        let mut header = TrackHeader::default();
+        // The line that each setting was given on, so that they can't be given twice
+        let mut set_on: HashMap<&str, usize> = HashMap::new();
+        let mut offset = 0;
+
+        for (i, line) in input.split_inclusive('\n').enumerate() {
+            // Lines are indexed from 1 in error messages
+            let line_no = i + 1;
+            if line.starts_with('+') {
+                return Ok((header, &input[offset..], line_no));
+            }
+            offset += line.len();
+
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let eq_idx = line.find('=').ok_or_else(|| {
+                format!(
+                    "track header error on line {}: expected 'key = value'",
+                    line_no
+                )
+            })?;
+            let key = line[..eq_idx].trim();
+            let value = line[eq_idx + 1..].trim();
+
+            if let Some(prev) = set_on.insert(key, line_no) {
+                return Err(format!(
+                    "track header error on line {}: '{}' was already set on line {}",
+                    line_no, key, prev
+                ));
+            }
+
+            match key {
+                "name" => header.name = Some(parse_text(line_no, value)?),
+                "author" => header.author = Some(parse_text(line_no, value)?),
+                "laps" => {
+                    header.laps = parse_whole(line_no, value, "laps")?;
+                    if header.laps < 1 {
+                        return Err(format!(
+                            "track header error on line {}: there has to be at least one lap",
+                            line_no
+                        ));
+                    }
+                }
+                "tile_size" => {
+                    header.tile_size = parse_num(line_no, value)?;
+                    if header.tile_size < CAR_LENGTH {
+                        return Err(format!(
+                            "track header error on line {}: tiles have to be at least as big as \
+                            the car ({}), but they're {}",
+                            line_no, CAR_LENGTH, value
+                        ));
+                    }
+                }
+                "start_angle" => {
+                    header.start_angle = Angle::from_degrees(parse_num(line_no, value)?)
+                }
+                "tick_limit" => {
+                    header.tick_limit = parse_whole(line_no, value, "ticks")?;
+                    if header.tick_limit < 1 {
+                        return Err(format!(
+                            "track header error on line {}: the tick limit has to be at least 1",
+                            line_no
+                        ));
+                    }
+                }
+                "setup_budget" => header.setup_limits.budget = parse_points(line_no, value)?,
+                "max_setup_points" => {
+                    header.setup_limits.max_points = parse_points(line_no, value)?
+                }
+                _ => {
+                    return Err(format!(
+                        "track header error on line {}: unknown setting '{}'",
+                        line_no, key
+                    ))
+                }
+            }
+        }
+
+        Err("expected the track's grid, starting with '+'".to_owned())
+    }
+}
+
+fn parse_text(line_no: usize, value: &str) -> Result<String, String> {
+    if value.is_empty() {
+        return Err(format!(
+            "track header error on line {}: expected some text after '='",
+            line_no
+        ));
+    }
+
+    Ok(value.to_owned())
+}
+
+// Rust happily parses "NaN" and "inf" as numbers, but neither of them makes sense for any setting
+fn parse_num(line_no: usize, value: &str) -> Result<f32, String> {
+    let num: f32 = value.parse().map_err(|_| {
+        format!(
+            "track header error on line {}: expected a number, found '{}'",
+            line_no, value
+        )
+    })?;
+
+    if !num.is_finite() {
+        return Err(format!(
+            "track header error on line {}: {} is not finite",
+            line_no, value
+        ));
+    }
+    Ok(num)
+}
+
+// Parses a number of setup points, which can't be more than `SETUP_POINTS_CAP`
+fn parse_points(line_no: usize, value: &str) -> Result<u32, String> {
+    let points = parse_whole(line_no, value, "points")?;
+    if points > SETUP_POINTS_CAP {
+        return Err(format!(
+            "track header error on line {}: a track can allow at most {} setup points, but this \
+            is {}",
+            line_no, SETUP_POINTS_CAP, points
+        ));
+    }
+    Ok(points)
+}
+
+fn parse_whole<T: FromStr>(line_no: usize, value: &str, what: &str) -> Result<T, String> {
+    value.parse().map_err(|_| {
+        format!(
+            "track header error on line {}: expected a whole number of {}, found '{}'",
+            line_no, what, value
+        )
+    })
+}